@@ -0,0 +1,140 @@
+// Constant-product (x * y = k) pool math. Every division rounds down, so
+// amounts paid out of the pool never exceed what the invariant allows and k
+// can only grow. Functions return None on overflow or an empty reserve.
+
+// a * b / c with a 256-bit intermediate product; None if c is 0 or the
+// quotient doesn't fit in 128 bits
+pub fn mul_div(a: u128, b: u128, c: u128) -> Option<u128> {
+    if c == 0 {
+        return None;
+    }
+    if let Some(product) = a.checked_mul(b) {
+        return Some(product / c);
+    }
+    let (hi, lo) = wide_mul(a, b);
+    if hi >= c {
+        return None;
+    }
+    // Long division of (hi, lo) by c, one bit of lo at a time. The remainder
+    // stays below c, and the carry flag covers the bit shifted out of it.
+    let mut rem = hi;
+    let mut quotient = 0u128;
+    for i in (0..128).rev() {
+        let carry = rem >> 127;
+        rem = (rem << 1) | ((lo >> i) & 1);
+        quotient <<= 1;
+        if carry == 1 || rem >= c {
+            rem = rem.wrapping_sub(c);
+            quotient |= 1;
+        }
+    }
+    Some(quotient)
+}
+
+fn wide_mul(a: u128, b: u128) -> (u128, u128) {
+    let mask = u64::MAX as u128;
+    let (a_hi, a_lo) = (a >> 64, a & mask);
+    let (b_hi, b_lo) = (b >> 64, b & mask);
+    let lo_lo = a_lo * b_lo;
+    let hi_lo = a_hi * b_lo;
+    let lo_hi = a_lo * b_hi;
+    let hi_hi = a_hi * b_hi;
+    let mid = (lo_lo >> 64) + (hi_lo & mask) + (lo_hi & mask);
+    let lo = (lo_lo & mask) | (mid << 64);
+    let hi = hi_hi + (hi_lo >> 64) + (lo_hi >> 64) + (mid >> 64);
+    (hi, lo)
+}
+
+pub fn isqrt(n: u128) -> u128 {
+    if n < 2 {
+        return n;
+    }
+    let mut x = 1u128 << (128 - n.leading_zeros()).div_ceil(2);
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+// Shares minted for the first deposit: the geometric mean of the two sides
+pub fn initial_shares(token_amount: u128, icp_amount: u128) -> u128 {
+    match token_amount.checked_mul(icp_amount) {
+        Some(product) => isqrt(product),
+        None => isqrt(token_amount).saturating_mul(isqrt(icp_amount)),
+    }
+}
+
+// Shares for a later deposit, priced on whichever side is the smaller share
+// of its reserve; the surplus on the other side stays in the pool
+pub fn deposit_shares(
+    token_amount: u128,
+    icp_amount: u128,
+    token_reserve: u128,
+    icp_reserve: u128,
+    total_shares: u128,
+) -> Option<u128> {
+    let by_token = mul_div(token_amount, total_shares, token_reserve)?;
+    let by_icp = mul_div(icp_amount, total_shares, icp_reserve)?;
+    Some(by_token.min(by_icp))
+}
+
+// Reserves paid out for burning `shares`
+pub fn withdrawal(shares: u128, token_reserve: u128, icp_reserve: u128, total_shares: u128) -> Option<(u128, u128)> {
+    Some((mul_div(token_reserve, shares, total_shares)?, mul_div(icp_reserve, shares, total_shares)?))
+}
+
+// Output of a swap after the pool fee is taken from the input. The fee stays
+// in the reserves, which is how liquidity providers are paid.
+pub fn swap_output(amount_in: u128, reserve_in: u128, reserve_out: u128, fee_bps: u16) -> Option<u128> {
+    if reserve_in == 0 || reserve_out == 0 {
+        return None;
+    }
+    let in_after_fee = mul_div(amount_in, 10_000 - fee_bps as u128, 10_000)?;
+    mul_div(reserve_out, in_after_fee, reserve_in.checked_add(in_after_fee)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mul_div_carries_a_product_wider_than_128_bits() {
+        assert_eq!(mul_div(6, 7, 4), Some(10));
+        assert_eq!(mul_div(u128::MAX, u128::MAX, u128::MAX), Some(u128::MAX));
+        assert_eq!(mul_div(u128::MAX, 6, 3), None);
+        assert_eq!(mul_div(u128::MAX, 6, 12), Some(u128::MAX / 2));
+        assert_eq!(mul_div(1, 1, 0), None);
+    }
+
+    #[test]
+    fn swaps_never_shrink_the_constant_product() {
+        let reserves = [(1_000, 1_000), (5_000_000, 7), (3, 9_000_000_000), (1 << 60, 1 << 61)];
+        for (reserve_in, reserve_out) in reserves {
+            for amount_in in [1, 2, 999, 1_000_000, 1 << 62] {
+                for fee_bps in [0, 30, 10_000] {
+                    let out = swap_output(amount_in, reserve_in, reserve_out, fee_bps).unwrap();
+                    assert!(out < reserve_out);
+                    let k = reserve_in * reserve_out;
+                    assert!((reserve_in + amount_in) * (reserve_out - out) >= k);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn swap_from_an_empty_reserve_is_refused() {
+        assert_eq!(swap_output(10, 0, 100, 30), None);
+        assert_eq!(swap_output(10, 100, 0, 30), None);
+    }
+
+    #[test]
+    fn withdrawing_every_share_returns_the_whole_reserve() {
+        let shares = initial_shares(400, 900);
+        assert_eq!(shares, 600);
+        assert_eq!(withdrawal(shares, 400, 900, shares), Some((400, 900)));
+        assert_eq!(withdrawal(shares / 3, 400, 900, shares), Some((133, 300)));
+    }
+}
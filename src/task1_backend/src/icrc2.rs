@@ -1,42 +1,623 @@
-use ic_cdk::export::candid::{CandidType, Deserialize};
-use std::collections::{HashMap, HashSet};
-use ic_cdk::export::Principal;
+use crate::amm;
+use crate::merkle;
+use candid::{CandidType, Deserialize, Principal};
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+mod env;
+mod legacy;
+#[cfg(test)]
+mod tests;
 
 #[derive(CandidType, Deserialize, Clone)]
 pub struct Token {
     pub symbol: String,
     pub name: String,
-    pub total_supply: u64,
+    pub total_supply: u128,
     pub owner: Principal,
     pub decimals: u8,
 }
 
+#[derive(CandidType, Deserialize, Clone)]
+pub struct DashboardData {
+    pub balance: u128,
+    pub spendable: u128,
+    pub allowances: Vec<(Principal, u128)>, // In the order the spenders were requested
+    pub token: Token,
+    pub fee: u128,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AccountSummary {
+    pub balance: u128,
+    pub spendable: u128,
+    pub locked: u128, // Time-locked amount not yet matured
+    pub held: u128, // Escrowed by an in-flight transfer_and_notify
+    pub outgoing_allowances_total: u128, // Sum of live approvals this account has granted
+    pub tx_count: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug, Default)]
+pub enum TxKind {
+    #[default]
+    Transfer,
+    TransferFrom,
+    Mint,
+    Reap,
+    Burn,
+    Approve,
+    Reclaim,
+    ForceTransfer,
+    Rollback, // Reverses a notified transfer the receiver rejected
+}
+
 #[derive(CandidType, Deserialize, Clone)]
 pub struct TransactionRecord {
     pub from: Principal,
     pub to: Principal,
-    pub amount: u64,
-    pub post_balance_from: u64,
-    pub post_balance_to: u64,
+    pub amount: u128,
+    pub post_balance_from: u128,
+    pub post_balance_to: u128,
     pub cycles_burnt: u64,
     pub reason: String,
+    #[serde(default)]
+    pub kind: TxKind,
+    #[serde(default)]
+    pub timestamp: u64, // Nanoseconds since epoch, from env::time()
+    pub memo: Option<Vec<u8>>,
+    #[serde(default)]
+    pub fee: u128, // Paid by `from` to the fee collector on top of `amount`
+    pub caller: Option<Principal>, // Principal whose call produced the record, e.g. the spender
+    pub authorized_by: Option<Principal>, // Whose authority allowed it; None for ledger-initiated moves
+}
+#[derive(CandidType, Deserialize, Clone)]
+pub struct MintProposal {
+    pub id: u64,
+    pub to: Principal,
+    pub amount: u128,
+    pub proposer: Principal,
+    pub approvals: Vec<Principal>,
+    pub expires_at: u64,
+    pub custody_ref: Option<String>, // Off-chain issuance reference, written to the mint records' memo
+}
+
+#[derive(CandidType, Deserialize, Clone)]
+pub struct FailedOp {
+    pub operation: String,
+    pub caller: Principal,
+    pub error: String,
+    pub timestamp: u64,
+}
+
+// Default size of the failure log; oldest entries are dropped beyond it
+const FAILED_OPS_CAPACITY: u64 = 1_000;
+const MAX_EVENT_LOG_CAPACITY: u64 = 100_000;
+
+// Per-operation switches; a disabled operation returns "Operation disabled"
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FeatureFlags {
+    pub transfer: bool,
+    pub approve: bool,
+    pub transfer_from: bool,
+    pub mint: bool,
+    pub burn: bool,
+    pub force_transfer: bool, // Owner-ordered transfers; off unless deliberately enabled
+}
+
+impl Default for FeatureFlags {
+    fn default() -> Self {
+        Self {
+            transfer: true,
+            approve: true,
+            transfer_from: true,
+            mint: true,
+            burn: true,
+            force_transfer: false,
+        }
+    }
+}
+
+impl FeatureFlags {
+    // ICRC-2 needs both halves of the allowance flow
+    pub fn allowances_enabled(&self) -> bool {
+        self.approve && self.transfer_from
+    }
+
+    // Switch for an operation kind; reaps, reclaims and rollbacks have none and are always on
+    fn enabled(&self, kind: TxKind) -> bool {
+        match kind {
+            TxKind::Transfer => self.transfer,
+            TxKind::TransferFrom => self.transfer_from,
+            TxKind::Approve => self.approve,
+            TxKind::Mint => self.mint,
+            TxKind::Burn => self.burn,
+            TxKind::ForceTransfer => self.force_transfer,
+            TxKind::Reap | TxKind::Reclaim | TxKind::Rollback => true,
+        }
+    }
+}
+
+// Who bears the transfer fee. When the recipient does, the sender is debited
+// exactly the amount and the fee comes out of what the recipient receives.
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum FeePayer {
+    #[default]
+    Sender,
+    Recipient,
+}
+
+// What transfer does while transfers are paused. Queued transfers run in
+// order on unpause, each re-validated against the balances then.
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum PauseMode {
+    #[default]
+    Reject,
+    Queue,
+}
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct QueuedTransfer {
+    pub from: Principal,
+    pub to: Principal,
+    pub amount: u128,
+    pub memo: Option<Vec<u8>>,
+    pub queued_at: u64,
+}
+
+const TRANSFER_QUEUE_CAPACITY: usize = 1_000;
+
+// 10^decimals has to fit in an amount for display and fee conversion
+fn check_decimals(decimals: u8) -> Result<(), String> {
+    if 10u128.checked_pow(decimals as u32).is_none() {
+        return Err("Decimals must be at most 38".to_string());
+    }
+    Ok(())
+}
+
+fn require_enabled(enabled: bool) -> Result<(), String> {
+    if enabled {
+        Ok(())
+    } else {
+        Err("Operation disabled".to_string())
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LedgerType {
+    Icrc1,
+    Icrc2,
+}
+
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct StandardRecord {
+    pub name: String,
+    pub url: String,
+}
+
+// Tokens that count towards an account's balance but can't be spent before `unlock_at`
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TimeLock {
+    pub amount: u128,
+    pub unlock_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RateLimit {
+    pub max_ops: u32,
+    pub window_ns: u64,
+}
+
+// Pre-flight outcome of a transfer; post_balance_from is the current balance on failure
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct SimulationResult {
+    pub would_succeed: bool,
+    pub fee: u128,
+    pub post_balance_from: u128,
+    pub error: Option<String>,
+}
+
+// Concentration of holdings, in basis points so results are exact integers.
+// top1/top10 are the shares held by the largest 1% and 10% of holders
+// (at least one holder each); gini runs from 0 (equal) to 10000.
+#[derive(CandidType, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct DistributionStats {
+    pub holder_count: u64,
+    pub top1_bps: u16,
+    pub top10_bps: u16,
+    pub gini_bps: u16,
+}
+
+// Shortest inactivity period the owner may configure for reclaim_inactive
+const MIN_INACTIVITY_PERIOD_NS: u64 = 365 * 24 * 60 * 60 * 1_000_000_000;
+
+// How long a positive answer from the user registry is trusted
+const REGISTRY_CACHE_TTL_NS: u64 = 5 * 60 * 1_000_000_000;
+
+// distribution_stats sorts every holder, so it refuses beyond this many
+const DISTRIBUTION_STATS_MAX_HOLDERS: usize = 100_000;
+
+// Counts of the expired entries removed by one `reap_expired` sweep, plus
+// the auto-compounding stakes it restaked rewards into and any scheduled burn
+#[derive(CandidType, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct ReapSummary {
+    pub allowances: u64,
+    pub time_locks: u64,
+    pub account_locks: u64,
+    pub mint_proposals: u64,
+    pub stakes_compounded: u64,
+    pub deflation_burned: u128,
+}
+
+// Burns rate_bps of the treasury (the fee collector's spendable balance) once
+// per interval, on the first maintenance pass after the interval elapses
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct DeflationSchedule {
+    pub rate_bps: u16,
+    pub interval_ns: u64,
 }
+
+// Everything awaiting a confirmation, callback or expiry. Holds and pending
+// burns and reclaims have no expiry; mint proposals carry their own.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct PendingOps {
+    pub mint_proposals: Vec<MintProposal>,
+    pub holds: Vec<(Principal, u128)>, // Escrow awaiting a transfer_and_notify callback
+    pub burns: Vec<(Principal, u128)>, // Awaiting confirm_burn
+    pub reclaims: Vec<(Principal, Principal)>, // (inactive account, destination) awaiting reclaim_inactive
+}
+
+// Token metadata plus the subnet certificate over its hash; see metadata_hash
+// for what the certified data commits to. The certificate is None when the
+// query runs replicated, e.g. from another canister's update call.
+#[derive(CandidType, Deserialize, Clone)]
+pub struct CertifiedMetadata {
+    pub symbol: String,
+    pub name: String,
+    pub decimals: u8,
+    pub certificate: Option<Vec<u8>>,
+}
+
+// Staked tokens stay in the balance but can't be spent until unstaked.
+// Rewards accrue from `since`; `accrued` holds what was earned before the
+// last change to the total staked or the emission. Auto-compounding stakes have their
+// rewards restaked by each maintenance pass instead of left to claim.
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct StakeInfo {
+    pub amount: u128,
+    pub since: u64,
+    pub accrued: u128,
+    pub auto_compound: bool,
+}
+
+const YEAR_NS: u64 = 365 * 24 * 60 * 60 * 1_000_000_000;
+
+// Outcome of a batch_transfer; indices refer to positions in the request
+#[derive(CandidType, Deserialize, Clone, Default, PartialEq, Eq, Debug)]
+pub struct BatchResult {
+    pub succeeded: Vec<(u64, u128)>, // (index, amount requested)
+    pub failed: Vec<(u64, String)>,
+    pub total_transferred: u128, // Credited to recipients; queued entries add nothing yet
+    pub total_fees: u128,
+}
+
+impl BatchResult {
+    // `outcome` carries the (credited, fee) the transfer actually moved
+    fn push(&mut self, index: u64, amount: u128, outcome: Result<(u128, u128), String>) {
+        match outcome {
+            Ok((credited, fee)) => {
+                self.succeeded.push((index, amount));
+                self.total_transferred = self.total_transferred.saturating_add(credited);
+                self.total_fees = self.total_fees.saturating_add(fee);
+            }
+            Err(e) => self.failed.push((index, e)),
+        }
+    }
+}
+
+// Every batch entry may await a spend authorizer, so batches stay small
+const BATCH_TRANSFER_MAX: usize = 100;
+
+// A pro-rata distribution writes one record per holder in a single message
+const DISTRIBUTION_MAX_HOLDERS: usize = 1_000;
+
+// ICRC-1 account. The ledger keeps a single balance per principal, so only
+// the default subaccount (None or all zeroes) can hold tokens.
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<[u8; 32]>,
+}
+
+impl Account {
+    pub fn is_default_subaccount(&self) -> bool {
+        self.subaccount.is_none_or(|sub| sub == [0u8; 32])
+    }
+}
+
+impl TxKind {
+    // Stable tag used in record hashes; never renumber existing variants
+    fn tag(&self) -> u8 {
+        match self {
+            TxKind::Transfer => 0,
+            TxKind::TransferFrom => 1,
+            TxKind::Mint => 2,
+            TxKind::Reap => 3,
+            TxKind::Burn => 4,
+            TxKind::Approve => 5,
+            TxKind::Reclaim => 6,
+            TxKind::ForceTransfer => 7,
+            TxKind::Rollback => 8,
+        }
+    }
+}
+
+// Hash over a record's canonical fields in a fixed, length-prefixed layout:
+// from, to, amount, timestamp, kind, memo
+pub fn record_hash(record: &TransactionRecord) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for principal in [&record.from, &record.to] {
+        let bytes = principal.as_slice();
+        hasher.update([bytes.len() as u8]);
+        hasher.update(bytes);
+    }
+    hasher.update(record.amount.to_be_bytes());
+    hasher.update(record.timestamp.to_be_bytes());
+    hasher.update([record.kind.tag()]);
+    match &record.memo {
+        Some(memo) => {
+            hasher.update([1]);
+            hasher.update((memo.len() as u64).to_be_bytes());
+            hasher.update(memo);
+        }
+        None => hasher.update([0]),
+    }
+    hasher.finalize().into()
+}
+
+// Certified data for the metadata: length-prefixed symbol and name, then decimals
+pub fn metadata_hash(symbol: &str, name: &str, decimals: u8) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for field in [symbol, name] {
+        hasher.update((field.len() as u64).to_be_bytes());
+        hasher.update(field.as_bytes());
+    }
+    hasher.update([decimals]);
+    hasher.finalize().into()
+}
+
+// Renders a base-unit amount as a decimal string, e.g. 1_000_000 with 6
+// decimals as "1.000000". Pure integer formatting, so no float rounding.
+pub fn format_amount(amount: u128, decimals: u8) -> String {
+    let digits = amount.to_string();
+    let decimals = decimals as usize;
+    if decimals == 0 {
+        return digits;
+    }
+    let padded = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fraction) = padded.split_at(padded.len() - decimals);
+    format!("{}.{}", whole, fraction)
+}
+
+// Update calls are charged 4 cycles per 10 instructions on a 13-node subnet
+const CYCLES_PER_TEN_INSTRUCTIONS: u64 = 4;
+
+thread_local! {
+    // (time, caller, instruction counter) when the last record was metered.
+    // Heap-only, since it only ever describes the message in flight.
+    static METERED_MARK: std::cell::Cell<Option<(u64, Principal, u64)>> = const { std::cell::Cell::new(None) };
+}
+
+fn instructions_to_cycles(instructions: u64) -> u64 {
+    instructions.saturating_mul(CYCLES_PER_TEN_INSTRUCTIONS) / 10
+}
+
+// Cycles for the instructions executed since the previous record of the
+// current message, or since the message began, so a message that writes
+// several records is charged once in total. The counter restarts with every
+// message; a mark from another time or caller, or above the counter, belongs
+// to an earlier message.
+fn metered_cycles() -> u64 {
+    let (time, caller, counter) = (env::time(), env::caller(), env::instruction_counter());
+    let since = match METERED_MARK.with(std::cell::Cell::get) {
+        Some((t, c, mark)) if t == time && c == caller && mark <= counter => mark,
+        _ => 0,
+    };
+    METERED_MARK.with(|m| m.set(Some((time, caller, counter))));
+    instructions_to_cycles(counter) - instructions_to_cycles(since)
+}
+
+// Each block hash commits to its parent, so the tip hash covers all history
+fn chain_hash(parent: Option<&[u8; 32]>, record: &TransactionRecord) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(parent.unwrap_or(&[0u8; 32]));
+    hasher.update(record_hash(record));
+    hasher.finalize().into()
+}
+
+// How percentage-based fees and burns treat the fractional unit.
+// Whatever is rounded away from the cut stays with the remainder, so the
+// cut plus the remainder always equals the original amount and no units
+// leave supply accounting through rounding.
+#[derive(CandidType, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RoundingMode {
+    Down,
+    Up,
+    Nearest,
+}
+
+// Token/ICP constant-product pool. The token side sits in the ledger
+// canister's own balance and the ICP side in its account on the ICP ledger.
+#[derive(CandidType, Deserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct PoolInfo {
+    pub token_reserve: u128,
+    pub icp_reserve: u128,
+    pub total_shares: u128,
+    pub fee_bps: u16,
+}
+
+// ICRC-1/2 arguments for calls to the ICP ledger
+#[derive(CandidType, Deserialize)]
+struct IcpTransferArg {
+    from_subaccount: Option<[u8; 32]>,
+    to: Account,
+    amount: u128,
+    fee: Option<u128>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct IcpTransferFromArgs {
+    spender_subaccount: Option<[u8; 32]>,
+    from: Account,
+    to: Account,
+    amount: u128,
+    fee: Option<u128>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+// Every owner-tunable parameter in one place. Per-account lists (whitelists,
+// exemptions, minters) have their own endpoints, and the maintenance timer is
+// left to set_maintenance_interval since changing it restarts the timer.
+#[derive(CandidType, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct LedgerConfig {
+    pub fee: u128,
+    pub fee_free_below: u128,
+    pub fee_payer: FeePayer,
+    pub approve_fee: u128,
+    pub min_approval_amount: u128,
+    pub transfer_from_fee: Option<u128>,
+    pub fiat_fee: Option<u64>,
+    pub price_oracle: Option<Principal>,
+    pub fee_collector: Principal,
+    pub rounding_mode: RoundingMode,
+    pub mint_fee_bps: u16,
+    pub mint_threshold: u32,
+    pub max_minters: Option<u32>,
+    pub minter_activation_delay_ns: u64,
+    pub require_custody_ref: bool,
+    pub user_registry: Option<Principal>,
+    pub mint_hook: Option<Principal>,
+    pub staking_emission: u128,
+    pub max_tx_amount: Option<u128>,
+    pub max_wallet: Option<u128>,
+    pub max_allowance: Option<u128>,
+    pub existential_deposit: u128,
+    pub require_memo: bool,
+    pub memo_uniqueness_window_ns: Option<u64>,
+    pub require_recipient_whitelist: bool,
+    pub trading_enabled: bool,
+    pub accept_direct_deposits: bool,
+    pub hide_frozen_balances: bool,
+    pub large_burn_threshold: Option<u128>,
+    pub burn_account: Principal,
+    pub inactivity_period_ns: Option<u64>,
+    pub rate_limit: Option<RateLimit>,
+    pub feature_flags: FeatureFlags,
+    pub pause_mode: PauseMode,
+    pub deflation: Option<DeflationSchedule>,
+    pub icp_ledger: Option<Principal>,
+    pub icp_fee: u128,
+    pub pool_fee_bps: u16,
+    pub event_log_capacity: u64,
+}
+
+// Saved whole by pre_upgrade. Candid can't default a field an older snapshot
+// lacks, so any change to this layout needs a STATE_VERSION bump and a decode
+// struct for the previous layout in `legacy`.
+#[derive(CandidType, Deserialize)]
 pub struct TokenICRC2 {
-    balances: HashMap<Principal, u64>,
-    allowances: HashMap<Principal, HashMap<Principal, u64>>,
+    balances: HashMap<Principal, u128>,
+    allowances: HashMap<Principal, HashMap<Principal, u128>>,
+    allowance_expiries: HashMap<(Principal, Principal), u64>, // (owner, spender) -> expiry in ns
     minters: HashSet<Principal>, 
+    max_minters: Option<u32>, // Cap on the minter set; None is unlimited
+    minter_granted_at: HashMap<Principal, u64>, // When add_minter granted each minter; older grants aren't tracked
+    minter_activation_delay_ns: u64, // Wait after a grant before a minter can mint; the owner is exempt
     owner: Principal,
-    total_supply: u64,
+    total_supply: u128,
     decimals: u8,
     name: String,
     symbol: String,
     burnt_cycles: u64,
     transaction_history: Vec<TransactionRecord>,
+    account_index: HashMap<Principal, Vec<u64>>, // Record indices touching each principal, ascending
+    held: HashMap<Principal, u128>, // Escrowed amounts still counted in balances
+    time_locks: HashMap<Principal, Vec<TimeLock>>,
+    stakes: HashMap<Principal, StakeInfo>,
+    staking_emission: u128, // Rewards minted per year, shared by stakes pro rata to their amounts
+    rounding_mode: RoundingMode,
+    max_allowance: Option<u128>, // Ceiling on a spender's allowance summed over all owners
+    locked_until: HashMap<Principal, u64>, // Temporary send locks, cleared once expired
+    hide_frozen_balances: bool, // Report zero balances for accounts under a send lock
+    fee_collector: Principal,
+    existential_deposit: u128, // Minimum nonzero balance a sender may be left with
+    burn_account: Principal, // Recorded as `to` on burns; burned tokens are destroyed, not credited
+    burned_total: u128,
+    deflation: Option<DeflationSchedule>,
+    last_deflation_burn: u64, // When the schedule last burned, or was set
+    large_burn_threshold: Option<u128>, // Burns above this need propose_burn then confirm_burn
+    pending_burns: HashMap<Principal, u128>, // Proposed large burn per account, awaiting confirmation
+    inactivity_period_ns: Option<u64>, // Silence required before reclaim_inactive; None disables it
+    pending_reclaims: HashMap<Principal, Principal>, // Inactive account -> proposed destination
+    accept_direct_deposits: bool, // Allow plain transfers to the ledger canister itself
+    mint_threshold: u32, // Distinct minter approvals needed; above 1, mints go through proposals
+    mint_fee_bps: u16, // Share of each mint routed to the fee collector
+    mint_proposal_ttl_ns: u64,
+    mint_proposals: HashMap<u64, MintProposal>,
+    next_proposal_id: u64,
+    failed_operations: VecDeque<FailedOp>,
+    event_log_capacity: u64, // Failure log entries kept before the oldest are dropped
+    dropped_events: u64, // Lifetime count of log entries dropped to stay under the cap
+    error_counts: HashMap<String, u64>, // Lifetime count per error message, not bounded by the log
+    require_memo: bool,
+    memo_uniqueness_window_ns: Option<u64>, // Reject a memo already sent to the same recipient this recently
+    require_custody_ref: bool, // Mints must cite an off-chain issuance reference
+    max_tx_amount: Option<u128>, // Anti-whale cap on a single transfer
+    exempt_accounts: HashSet<Principal>, // Not subject to transfer size or wallet limits
+    require_recipient_whitelist: bool, // Only whitelisted accounts may receive transfers
+    recipient_whitelist: HashSet<Principal>,
+    trading_enabled: bool, // Before launch only the owner and whitelisted senders may transfer
+    trading_whitelist: HashSet<Principal>,
+    max_wallet: Option<u128>, // Cap on any non-exempt recipient's resulting balance
+    fee: u128, // Flat transfer fee credited to the fee collector
+    fee_free_below: u128, // Transfers of less than this pay no fee
+    fee_payer: FeePayer,
+    approve_fee: u128, // Charged to the approver on each approve
+    min_approval_amount: u128, // Smallest nonzero allowance approve accepts
+    transfer_from_fee: Option<u128>, // Fee for delegated transfers; None charges the transfer fee
+    price_oracle: Option<Principal>,
+    fiat_fee: Option<u64>, // Target fee in oracle quote units; None keeps the flat fee
+    cached_price: Option<(u64, u64)>, // (price, fetched_at ns)
+    price_ttl_ns: u64,
+    user_registry: Option<Principal>, // Asked `is_registered` before mints; None allows any recipient
+    mint_hook: Option<Principal>, // Notified with on_mint(to, amount, tx_index) after each mint record
+    registered_at: HashMap<Principal, u64>, // When the registry last confirmed each recipient
+    tip_hash: Option<[u8; 32]>, // Chained hash of the latest record
+    spend_authorizers: HashMap<Principal, Principal>, // Account -> canister consulted before it spends
+    rate_limit: Option<RateLimit>, // Aggregate per-principal cap across all operation types
+    recent_ops: HashMap<Principal, VecDeque<u64>>, // Op timestamps inside the current window
+    feature_flags: FeatureFlags,
+    paused_operations: HashSet<TxKind>,
+    pause_mode: PauseMode,
+    queued_transfers: VecDeque<QueuedTransfer>, // Transfers accepted while paused, oldest first
+    icp_ledger: Option<Principal>, // ICP side of the swap pool; None disables the pool
+    icp_fee: u128, // ICP ledger fee, taken out of ICP the pool pays out
+    pool_fee_bps: u16, // Swap fee left in the reserves for liquidity providers
+    token_reserve: u128,
+    icp_reserve: u128,
+    lp_shares: HashMap<Principal, u128>,
+    total_lp_shares: u128,
+    icp_owed: HashMap<Principal, u128>, // ICP payouts that failed, claimable with claim_icp
+    maintenance_interval_ns: Option<u64>, // Period of the reap_expired timer; None when stopped
 
 }
 
 impl TokenICRC2 {
-    pub fn new(owner: Principal, total_supply: u64, decimals: u8, name: String, symbol: String) -> Self {
+    pub fn new(owner: Principal, total_supply: u128, decimals: u8, name: String, symbol: String) -> Self {
         let mut balances = HashMap::new();
         let mut minters = HashSet::new();
         balances.insert(owner, total_supply);
@@ -44,7 +625,11 @@ impl TokenICRC2 {
         Self {
             balances,
             allowances: HashMap::new(),
+            allowance_expiries: HashMap::new(),
             minters,
+            max_minters: None,
+            minter_granted_at: HashMap::new(),
+            minter_activation_delay_ns: 0,
             owner,
             total_supply,
             decimals,
@@ -52,17 +637,265 @@ impl TokenICRC2 {
             symbol,
             burnt_cycles: 0,
             transaction_history: Vec::new(),
+            account_index: HashMap::new(),
+            held: HashMap::new(),
+            time_locks: HashMap::new(),
+            stakes: HashMap::new(),
+            staking_emission: 0,
+            rounding_mode: RoundingMode::Down,
+            max_allowance: None,
+            locked_until: HashMap::new(),
+            hide_frozen_balances: false,
+            fee_collector: owner,
+            existential_deposit: 0,
+            burn_account: Principal::management_canister(),
+            burned_total: 0,
+            deflation: None,
+            last_deflation_burn: 0,
+            large_burn_threshold: None,
+            pending_burns: HashMap::new(),
+            inactivity_period_ns: None,
+            pending_reclaims: HashMap::new(),
+            accept_direct_deposits: false,
+            mint_threshold: 1,
+            mint_fee_bps: 0,
+            mint_proposal_ttl_ns: 24 * 60 * 60 * 1_000_000_000,
+            mint_proposals: HashMap::new(),
+            next_proposal_id: 0,
+            failed_operations: VecDeque::new(),
+            event_log_capacity: FAILED_OPS_CAPACITY,
+            dropped_events: 0,
+            error_counts: HashMap::new(),
+            require_memo: false,
+            memo_uniqueness_window_ns: None,
+            require_custody_ref: false,
+            max_tx_amount: None,
+            exempt_accounts: HashSet::new(),
+            require_recipient_whitelist: false,
+            recipient_whitelist: HashSet::new(),
+            trading_enabled: true,
+            trading_whitelist: HashSet::new(),
+            max_wallet: None,
+            fee: 0,
+            fee_free_below: 0,
+            fee_payer: FeePayer::Sender,
+            approve_fee: 0,
+            min_approval_amount: 0,
+            transfer_from_fee: None,
+            price_oracle: None,
+            fiat_fee: None,
+            cached_price: None,
+            price_ttl_ns: 5 * 60 * 1_000_000_000,
+            user_registry: None,
+            mint_hook: None,
+            registered_at: HashMap::new(),
+            tip_hash: None,
+            spend_authorizers: HashMap::new(),
+            rate_limit: None,
+            recent_ops: HashMap::new(),
+            feature_flags: FeatureFlags::default(),
+            paused_operations: HashSet::new(),
+            pause_mode: PauseMode::Reject,
+            queued_transfers: VecDeque::new(),
+            icp_ledger: None,
+            icp_fee: 10_000,
+            pool_fee_bps: 30,
+            token_reserve: 0,
+            icp_reserve: 0,
+            lp_shares: HashMap::new(),
+            total_lp_shares: 0,
+            icp_owed: HashMap::new(),
+            maintenance_interval_ns: None,
+
+        }
+    }
+    // Distributes the initial supply, which new() credits entirely to the owner.
+    // Allocations may not exceed total_supply; any remainder stays with the owner.
+    // Each account appears once, so a repeated entry is taken as a mistake.
+    pub fn allocate_genesis(&mut self, allocations: Vec<(Principal, u128)>) -> Result<(), String> {
+        let mut seen = HashSet::new();
+        if !allocations.iter().all(|(to, _)| seen.insert(*to)) {
+            return Err("Duplicate allocation".to_string());
+        }
+        let allocated = allocations
+            .iter()
+            .try_fold(0u128, |acc, (_, amount)| acc.checked_add(*amount))
+            .ok_or("Allocations overflow")?;
+        if allocated > self.total_supply {
+            return Err("Allocations exceed total supply".to_string());
+        }
+        let owner = self.owner;
+        self.balances.insert(owner, self.total_supply - allocated);
+        for (to, amount) in allocations {
+            *self.balances.entry(to).or_insert(0) += amount;
+            let record = TransactionRecord {
+                from: owner,
+                to,
+                amount,
+                post_balance_from: 0,
+                post_balance_to: self.balance_of(to),
+                cycles_burnt: 0,
+                reason: "Genesis allocation.".to_string(),
+                kind: TxKind::Mint,
+                timestamp: env::time(),
+                memo: None,
+                fee: 0,
+                caller: Some(env::caller()),
+                authorized_by: Some(owner),
+            };
+            self.push_record(record);
+        }
+        Ok(())
+    }
+
+    // What certify_metadata publishes as the canister's certified data
+    pub fn certified_data(&self) -> [u8; 32] {
+        metadata_hash(&self.symbol, &self.name, self.decimals)
+    }
+
+    pub fn certified_metadata(&self, certificate: Option<Vec<u8>>) -> CertifiedMetadata {
+        CertifiedMetadata {
+            symbol: self.symbol.clone(),
+            name: self.name.clone(),
+            decimals: self.decimals,
+            certificate,
+        }
+    }
+
+    pub fn token_info(&self) -> Token {
+        Token {
+            symbol: self.symbol.clone(),
+            name: self.name.clone(),
+            total_supply: self.total_supply,
+            owner: self.owner,
+            decimals: self.decimals,
+        }
+    }
+
+    pub fn dashboard(&self, account: Principal, spenders: Vec<Principal>) -> DashboardData {
+        DashboardData {
+            balance: self.balance_of(account),
+            spendable: self.spendable_balance(account),
+            allowances: spenders
+                .into_iter()
+                .map(|spender| (spender, self.allowance(account, spender)))
+                .collect(),
+            token: self.token_info(),
+            fee: self.effective_fee(),
+        }
+    }
 
+    pub fn account_summary(&self, account: Principal) -> AccountSummary {
+        let outgoing_allowances_total = self
+            .allowances
+            .get(&account)
+            .map(|spenders| {
+                spenders
+                    .keys()
+                    .fold(0u128, |acc, spender| acc.saturating_add(self.allowance(account, *spender)))
+            })
+            .unwrap_or(0);
+        AccountSummary {
+            balance: self.balance_of(account),
+            spendable: self.spendable_balance(account),
+            locked: self.time_locked_balance(account),
+            held: *self.held.get(&account).unwrap_or(&0),
+            outgoing_allowances_total,
+            tx_count: self.account_tx_count(account),
         }
     }
+
     pub fn get_owner(&self) -> Principal {
-        self.owner.clone()
+        self.owner
     }
-    pub fn balance_of(&self, user: Principal) -> u64 {
+    pub fn balance_of(&self, user: Principal) -> u128 {
         *self.balances.get(&user).unwrap_or(&0)
     }
 
-    pub fn allowance(&self, owner: Principal, spender: Principal) -> u64 {
+    // Reported balance; internal accounting always uses balance_of
+    pub fn icrc1_balance_of(&self, account: &Account) -> u128 {
+        let frozen = self.hide_frozen_balances && self.account_locked_until(account.owner).is_some();
+        if account.is_default_subaccount() && !frozen {
+            self.balance_of(account.owner)
+        } else {
+            0
+        }
+    }
+
+    pub fn hide_frozen_balances(&self) -> bool {
+        self.hide_frozen_balances
+    }
+
+    pub fn set_hide_frozen_balances(&mut self, hide: bool) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can change balance masking".to_string());
+        }
+        self.hide_frozen_balances = hide;
+        Ok(())
+    }
+
+    // Escrow holds, staked amounts, and time locks that haven't reached their unlock time
+    pub fn locked_balance(&self, account: Principal) -> u128 {
+        let held = *self.held.get(&account).unwrap_or(&0);
+        let staked = self.stakes.get(&account).map_or(0, |s| s.amount);
+        let pooled = if account == env::canister_id() { self.token_reserve } else { 0 };
+        held.saturating_add(staked)
+            .saturating_add(pooled)
+            .saturating_add(self.time_locked_balance(account))
+    }
+
+    pub fn time_locked_balance(&self, account: Principal) -> u128 {
+        let now = env::time();
+        self.time_locks
+            .get(&account)
+            .map(|locks| {
+                locks
+                    .iter()
+                    .filter(|l| l.unlock_at > now)
+                    .fold(0u128, |acc, l| acc.saturating_add(l.amount))
+            })
+            .unwrap_or(0)
+    }
+
+    pub fn time_locks(&self, account: Principal) -> Vec<TimeLock> {
+        let now = env::time();
+        self.time_locks
+            .get(&account)
+            .map(|locks| locks.iter().filter(|l| l.unlock_at > now).copied().collect())
+            .unwrap_or_default()
+    }
+
+    // Gross balance minus anything held in escrow
+    pub fn spendable_balance(&self, account: Principal) -> u128 {
+        self.balance_of(account).saturating_sub(self.locked_balance(account))
+    }
+
+    pub fn hold(&mut self, account: Principal, amount: u128) -> Result<(), String> {
+        if self.spendable_balance(account) < amount {
+            return Err("Insufficient spendable balance".to_string());
+        }
+        *self.held.entry(account).or_insert(0) += amount;
+        Ok(())
+    }
+
+    pub fn release_hold(&mut self, account: Principal, amount: u128) -> Result<(), String> {
+        let held = self.held.get(&account).copied().unwrap_or(0);
+        if held < amount {
+            return Err("Release exceeds held amount".to_string());
+        }
+        if held == amount {
+            self.held.remove(&account);
+        } else {
+            self.held.insert(account, held - amount);
+        }
+        Ok(())
+    }
+
+    // Expired approvals read as zero even before they are cleaned up
+    pub fn allowance(&self, owner: Principal, spender: Principal) -> u128 {
+        if self.allowance_expired(owner, spender) {
+            return 0;
+        }
         self.allowances
             .get(&owner)
             .and_then(|spenders| spenders.get(&spender))
@@ -70,10 +903,72 @@ impl TokenICRC2 {
             .unwrap_or(0)
     }
 
-    pub fn total_supply(&self) -> u64 {
+    // Spenders holding a live, nonzero allowance from `owner`
+    pub fn approval_count(&self, owner: Principal) -> u64 {
+        self.allowances.get(&owner).map_or(0, |spenders| {
+            spenders.keys().filter(|spender| self.allowance(owner, **spender) > 0).count() as u64
+        })
+    }
+
+    pub fn allowance_expires_at(&self, owner: Principal, spender: Principal) -> Option<u64> {
+        self.allowance_expiries.get(&(owner, spender)).copied()
+    }
+
+    fn allowance_expired(&self, owner: Principal, spender: Principal) -> bool {
+        self.allowance_expires_at(owner, spender)
+            .is_some_and(|expires_at| expires_at <= env::time())
+    }
+
+    pub fn total_supply(&self) -> u128 {
         self.total_supply
     }
 
+    // issued - sum(balances) - burned, where issued is everything ever minted.
+    // total_supply is kept net of burns, so issued is total_supply plus
+    // burned_total. Positive drift means supply with no owner; negative means
+    // balances that were never minted.
+    pub fn supply_drift(&self) -> i128 {
+        let as_drift = |v: u128| i128::try_from(v).unwrap_or(i128::MAX);
+        let issued = self.total_supply.saturating_add(self.burned_total);
+        let held = self.balances.values().fold(0u128, |acc, b| acc.saturating_add(*b));
+        as_drift(issued).saturating_sub(as_drift(held)).saturating_sub(as_drift(self.burned_total))
+    }
+
+    pub fn verify_supply_invariant(&self) -> bool {
+        self.supply_drift() == 0
+    }
+
+    pub fn total_supply_display(&self) -> String {
+        format_amount(self.total_supply, self.decimals)
+    }
+
+    // Inverse of format_amount: "1.5" with 6 decimals is 1_500_000. Only
+    // plain digits with at most one point are accepted. Input that doesn't
+    // fit in a u128, or is more precise than the token's decimals, is
+    // rejected rather than wrapped or cut short.
+    pub fn parse_amount(&self, input: &str) -> Result<u128, String> {
+        let input = input.trim();
+        let (whole, fraction) = match input.split_once('.') {
+            Some((whole, fraction)) if !fraction.is_empty() => (whole, fraction),
+            Some(_) => return Err("Invalid amount".to_string()),
+            None => (input, ""),
+        };
+        let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if whole.is_empty() || !is_digits(whole) || !is_digits(fraction) {
+            return Err("Invalid amount".to_string());
+        }
+        if fraction.len() > self.decimals as usize {
+            return Err("Too many decimal places".to_string());
+        }
+        let padding = std::iter::repeat_n(b'0', self.decimals as usize - fraction.len());
+        whole
+            .bytes()
+            .chain(fraction.bytes())
+            .chain(padding)
+            .try_fold(0u128, |value, digit| value.checked_mul(10)?.checked_add((digit - b'0') as u128))
+            .ok_or_else(|| "Amount too large".to_string())
+    }
+
     pub fn decimals(&self) -> u8 {
         self.decimals
     }
@@ -86,132 +981,5030 @@ impl TokenICRC2 {
         self.name.clone()
     }
 
-    pub fn transfer(&mut self, from: Principal, to: Principal, amount: u64) -> Result<(), String> {
-        let from_balance = self.balances.get(&from).unwrap_or(&0);
-        if *from_balance < amount {
-            return Err("Insufficient balance".to_string());
+    pub fn rounding_mode(&self) -> RoundingMode {
+        self.rounding_mode
+    }
+
+    pub fn set_rounding_mode(&mut self, mode: RoundingMode) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the rounding mode".to_string());
         }
-        *self.balances.entry(from).or_insert(0) -= amount;
-        *self.balances.entry(to).or_insert(0) += amount;
-        // Check for cycles burnt
-        let cycles_burnt = self.burnt_cycles; // assuming burnt_cycles represents the most recent burn
-        let reason = if cycles_burnt > 0 {
-            "Cycles were burnt due to transfer fees or maintenance costs.".to_string()
-        } else {
-            "No cycles were burnt as no transfer fees applied.".to_string()
-        };
+        self.rounding_mode = mode;
+        Ok(())
+    }
 
-        // Log the transaction
-        let record = TransactionRecord {
-            from,
-            to,
-            amount,
-            post_balance_from: self.balances.get(&from).copied().unwrap_or(0),
-            post_balance_to: self.balances.get(&to).copied().unwrap_or(0),
-            cycles_burnt,
-            reason,
+    // Portion of `amount` at `bps` basis points, rounded per the configured mode.
+    // Callers credit `amount - portion` elsewhere so the total is preserved.
+    // Whole multiples of 10_000 split exactly, so only the remainder is rounded
+    // and the multiplication can't overflow.
+    pub fn bps_portion(&self, amount: u128, bps: u16) -> u128 {
+        let bps = bps.min(10_000) as u128;
+        let numerator = amount % 10_000 * bps;
+        let rounded = match self.rounding_mode {
+            RoundingMode::Down => numerator / 10_000,
+            RoundingMode::Up => numerator.div_ceil(10_000),
+            RoundingMode::Nearest => (numerator + 5_000) / 10_000,
         };
-        self.transaction_history.push(record);
+        amount / 10_000 * bps + rounded
+    }
+
+    // Temporary cooling-off lock; unlike a permanent block it lapses on its own
+    pub fn lock_account_until(&mut self, account: Principal, until_ns: u64) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can lock accounts".to_string());
+        }
+        self.locked_until.insert(account, until_ns);
+        Ok(())
+    }
+
+    pub fn account_locked_until(&self, account: Principal) -> Option<u64> {
+        self.locked_until
+            .get(&account)
+            .copied()
+            .filter(|until| *until > env::time())
+    }
+
+    fn check_send_lock(&self, account: Principal) -> Result<(), String> {
+        if self.account_locked_until(account).is_some() {
+            return Err("Account is locked".to_string());
+        }
+        Ok(())
+    }
 
+    // Drops a send lock once it has lapsed; only called after validation passed
+    fn clear_expired_lock(&mut self, account: Principal) {
+        if self.locked_until.contains_key(&account) && self.account_locked_until(account).is_none() {
+            self.locked_until.remove(&account);
+        }
+    }
+
+    pub fn transfer(&mut self, from: Principal, to: Principal, amount: u128, memo: Option<Vec<u8>>) -> Result<(), String> {
+        self.transfer_with_fee(from, to, amount, self.transfer_fee(amount), memo).map(|_| ())
+    }
+
+    // `transfer` at a fee the caller has already fixed. Returns what moved,
+    // (credited, fee), or None if the transfer was queued instead.
+    fn transfer_with_fee(
+        &mut self,
+        from: Principal,
+        to: Principal,
+        amount: u128,
+        fee: u128,
+        memo: Option<Vec<u8>>,
+    ) -> Result<Option<(u128, u128)>, String> {
+        if self.feature_flags.transfer
+            && self.paused_operations.contains(&TxKind::Transfer)
+            && self.pause_mode == PauseMode::Queue
+        {
+            return self.queue_transfer(from, to, amount, memo).map(|()| None);
+        }
+        self.require_operation(TxKind::Transfer)?;
+        self.throttle(from)?;
+        self.check_memo(to, &memo)?;
+        self.apply_transfer(from, to, amount, fee, memo, TxKind::Transfer).map(Some)
+    }
+
+    // One batch_transfer entry, returning the (credited, fee) it moved; a
+    // queued entry has moved nothing yet
+    fn batch_transfer_entry(
+        &mut self,
+        from: Principal,
+        to: Principal,
+        amount: u128,
+        memo: Option<Vec<u8>>,
+    ) -> Result<(u128, u128), String> {
+        let moved = self.transfer_with_fee(from, to, amount, self.transfer_fee(amount), memo)?;
+        Ok(moved.unwrap_or((0, 0)))
+    }
+
+    // Transfers that couldn't go through today are turned away now rather than
+    // on unpause; passing here doesn't promise the transfer will still fit then
+    fn queue_transfer(&mut self, from: Principal, to: Principal, amount: u128, memo: Option<Vec<u8>>) -> Result<(), String> {
+        if self.queued_transfers.len() >= TRANSFER_QUEUE_CAPACITY {
+            return Err("Transfer queue is full".to_string());
+        }
+        self.throttle(from)?;
+        self.check_memo(to, &memo)?;
+        self.validate_transfer(from, to, amount, self.transfer_fee(amount))?;
+        self.queued_transfers.push_back(QueuedTransfer { from, to, amount, memo, queued_at: env::time() });
+        Ok(())
+    }
+
+    // Runs on unpause. Each transfer is checked again with the fee and
+    // balances of the moment; ones that no longer fit land in the failure log.
+    fn run_queued_transfers(&mut self) {
+        while let Some(q) = self.queued_transfers.pop_front() {
+            let result = self
+                .check_memo(q.to, &q.memo)
+                .and_then(|()| self.apply_transfer(q.from, q.to, q.amount, self.transfer_fee(q.amount), q.memo, TxKind::Transfer))
+                .map(|_| ());
+            let _ = self.log_failure("queued_transfer", q.from, result);
+        }
+    }
+
+    pub fn pause_mode(&self) -> PauseMode {
+        self.pause_mode
+    }
+
+    pub fn set_pause_mode(&mut self, mode: PauseMode) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the pause mode".to_string());
+        }
+        self.pause_mode = mode;
         Ok(())
     }
 
-    pub fn approve(&mut self, owner: Principal, spender: Principal, amount: u64) -> Result<(), String> {
-        self.allowances
-            .entry(owner)
-            .or_insert_with(HashMap::new)
-            .insert(spender, amount);
-        Ok(())
-    }
+    pub fn queued_transfers(&self) -> Vec<QueuedTransfer> {
+        self.queued_transfers.iter().cloned().collect()
+    }
+
+    // Runs the same checks as `transfer` without writing anything. Rate limits
+    // and the memo requirement depend on the actual call, so they aren't covered.
+    pub fn simulate_transfer(&self, from: Principal, to: Principal, amount: u128) -> SimulationResult {
+        let fee = self.transfer_fee(amount);
+        let balance = self.balance_of(from);
+        let outcome = self.require_operation(TxKind::Transfer)
+            .and_then(|_| self.validate_transfer(from, to, amount, fee));
+        match outcome {
+            Ok(debit) => SimulationResult {
+                would_succeed: true,
+                fee,
+                post_balance_from: if from == to { balance - fee } else { balance - debit },
+                error: None,
+            },
+            Err(e) => SimulationResult { would_succeed: false, fee, post_balance_from: balance, error: Some(e) },
+        }
+    }
+
+    // Sends everything `from` can spend, less the fee, leaving it at zero
+    pub fn transfer_all(&mut self, from: Principal, to: Principal, memo: Option<Vec<u8>>) -> Result<u128, String> {
+        let (amount, fee) = self.transfer_all_quote(from)?;
+        self.transfer_with_fee(from, to, amount, fee, memo)?;
+        Ok(amount)
+    }
+
+    pub fn transfer_all_amount(&self, from: Principal) -> Result<u128, String> {
+        self.transfer_all_quote(from).map(|(amount, _)| amount)
+    }
+
+    // (amount, fee) that spends all of `from`. A balance within the fee-free
+    // band goes whole. Above it the fee is charged even when the rest would
+    // fall inside the band, else the fee's worth would be left behind.
+    pub fn transfer_all_quote(&self, from: Principal) -> Result<(u128, u128), String> {
+        let spendable = self.spendable_balance(from);
+        if spendable > 0 && spendable <= self.fee_free_below {
+            return Ok((spendable, 0));
+        }
+        let fee = self.effective_fee();
+        if spendable <= fee {
+            return Err("Balance does not cover the fee".to_string());
+        }
+        match self.fee_payer {
+            FeePayer::Sender => Ok((spendable - fee, fee)),
+            FeePayer::Recipient => Ok((spendable, fee)),
+        }
+    }
+
+    // Counts an operation attempt against the caller's rolling window. Attempts
+    // count whether or not they later succeed, so rejected spam is throttled too.
+    fn throttle(&mut self, principal: Principal) -> Result<(), String> {
+        let Some(limit) = self.rate_limit else {
+            return Ok(());
+        };
+        let now = env::time();
+        let ops = self.recent_ops.entry(principal).or_default();
+        while ops.front().is_some_and(|t| now.saturating_sub(*t) >= limit.window_ns) {
+            ops.pop_front();
+        }
+        if ops.len() >= limit.max_ops as usize {
+            return Err("Rate limit exceeded".to_string());
+        }
+        ops.push_back(now);
+        Ok(())
+    }
+
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit
+    }
+
+    pub fn set_rate_limit(&mut self, limit: Option<RateLimit>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the rate limit".to_string());
+        }
+        self.rate_limit = limit;
+        self.recent_ops.clear();
+        Ok(())
+    }
+
+    pub fn spend_authorizer(&self, account: Principal) -> Option<Principal> {
+        self.spend_authorizers.get(&account).copied()
+    }
+
+    // Accounts opt themselves in (or out) of smart-wallet authorization
+    pub fn set_spend_authorizer(&mut self, account: Principal, authorizer: Option<Principal>) {
+        match authorizer {
+            Some(a) => self.spend_authorizers.insert(account, a),
+            None => self.spend_authorizers.remove(&account),
+        };
+    }
+
+    pub fn fee(&self) -> u128 {
+        self.fee
+    }
+
+    pub fn set_fee(&mut self, fee: u128) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the fee".to_string());
+        }
+        self.fee = fee;
+        Ok(())
+    }
+
+    // Fee on a plain transfer of `amount`; micro-payments below fee_free_below go free
+    pub fn transfer_fee(&self, amount: u128) -> u128 {
+        if amount < self.fee_free_below {
+            0
+        } else {
+            self.effective_fee()
+        }
+    }
+
+    pub fn fee_free_below(&self) -> u128 {
+        self.fee_free_below
+    }
+
+    pub fn set_fee_free_below(&mut self, threshold: u128) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the fee-free threshold".to_string());
+        }
+        self.fee_free_below = threshold;
+        Ok(())
+    }
+
+    pub fn fee_payer(&self) -> FeePayer {
+        self.fee_payer
+    }
+
+    pub fn set_fee_payer(&mut self, payer: FeePayer) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the fee payer".to_string());
+        }
+        self.fee_payer = payer;
+        Ok(())
+    }
+
+    // (debited from the sender, credited to the recipient) for sending `amount`
+    fn fee_split(&self, amount: u128, fee: u128) -> Result<(u128, u128), String> {
+        match self.fee_payer {
+            FeePayer::Sender => Ok((amount.checked_add(fee).ok_or("Amount overflow")?, amount)),
+            FeePayer::Recipient if fee > 0 && amount <= fee => {
+                Err("Amount must exceed the fee when the recipient pays it".to_string())
+            }
+            FeePayer::Recipient => Ok((amount, amount - fee)),
+        }
+    }
+
+    // What the recipient of a plain transfer of `amount` ends up with
+    pub fn received_amount(&self, amount: u128) -> u128 {
+        self.fee_split(amount, self.transfer_fee(amount)).map_or(0, |(_, credit)| credit)
+    }
+
+    // Fee charged on transfer_from: the delegated fee when set, else the transfer fee
+    pub fn transfer_from_fee(&self) -> u128 {
+        self.transfer_from_fee.unwrap_or_else(|| self.effective_fee())
+    }
+
+    pub fn set_transfer_from_fee(&mut self, fee: Option<u128>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the transfer_from fee".to_string());
+        }
+        self.transfer_from_fee = fee;
+        Ok(())
+    }
+
+    // Fee actually charged: the fiat-pegged fee converted at the cached oracle
+    // price while that price is fresh, otherwise the flat fee
+    pub fn effective_fee(&self) -> u128 {
+        match (self.fiat_fee, self.fresh_price()) {
+            (Some(fiat_fee), Some(price)) if price > 0 => 10u128
+                .checked_pow(self.decimals as u32)
+                .and_then(|scale| (fiat_fee as u128).checked_mul(scale))
+                .map_or(self.fee, |scaled| scaled / price as u128),
+            _ => self.fee,
+        }
+    }
+
+    fn fresh_price(&self) -> Option<u64> {
+        let (price, fetched_at) = self.cached_price?;
+        let age = env::time().saturating_sub(fetched_at);
+        (age < self.price_ttl_ns).then_some(price)
+    }
+
+    // The oracle to ask when the cached price needs refreshing. The price
+    // also backs balance valuation, so it's kept fresh even without a fiat fee.
+    pub fn price_refresh_due(&self) -> Option<Principal> {
+        let oracle = self.price_oracle?;
+        self.fresh_price().is_none().then_some(oracle)
+    }
+
+    // Balance valued at the cached oracle price, in the oracle's quote units
+    // (e8s for an ICP-quoted feed). Queries can't refresh the cache, so a
+    // stale or missing price is an error rather than a guess.
+    pub fn balance_value_in_icp(&self, account: Principal) -> Result<u64, String> {
+        if self.price_oracle.is_none() {
+            return Err("No price oracle configured".to_string());
+        }
+        let price = self.fresh_price().ok_or("Price is stale or unavailable")?;
+        let scale = 10u128.checked_pow(self.decimals as u32).ok_or("Value overflow")?;
+        let value = amm::mul_div(self.balance_of(account), price as u128, scale).ok_or("Value overflow")?;
+        u64::try_from(value).map_err(|_| "Value overflow".to_string())
+    }
+
+    pub fn record_price(&mut self, price: u64) {
+        self.cached_price = Some((price, env::time()));
+    }
+
+    pub fn price_oracle(&self) -> Option<Principal> {
+        self.price_oracle
+    }
+
+    pub fn set_price_oracle(&mut self, oracle: Option<Principal>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the price oracle".to_string());
+        }
+        self.price_oracle = oracle;
+        self.cached_price = None;
+        Ok(())
+    }
+
+    pub fn fiat_fee(&self) -> Option<u64> {
+        self.fiat_fee
+    }
+
+    // Fee in the oracle's quote units; the oracle prices one whole token in those units
+    pub fn set_fiat_fee(&mut self, fiat_fee: Option<u64>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the fiat fee".to_string());
+        }
+        self.fiat_fee = fiat_fee;
+        Ok(())
+    }
+
+    pub fn user_registry(&self) -> Option<Principal> {
+        self.user_registry
+    }
+
+    pub fn set_user_registry(&mut self, registry: Option<Principal>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the user registry".to_string());
+        }
+        self.user_registry = registry;
+        self.registered_at.clear();
+        Ok(())
+    }
+
+    // Registry to ask about `account`, or None when none is set or a recent
+    // confirmation is cached. Refusals aren't cached, so a user who registers
+    // after a rejected mint can be minted to straight away.
+    pub fn registry_check_due(&self, account: Principal) -> Option<Principal> {
+        let registry = self.user_registry?;
+        (!self.registration_fresh(account)).then_some(registry)
+    }
+
+    pub fn record_registration(&mut self, account: Principal) {
+        self.registered_at.insert(account, env::time());
+    }
+
+    fn registration_fresh(&self, account: Principal) -> bool {
+        self.registered_at
+            .get(&account)
+            .is_some_and(|at| env::time().saturating_sub(*at) < REGISTRY_CACHE_TTL_NS)
+    }
+
+    // Spends from `from` on behalf of `spender`, consuming the allowance
+    pub fn transfer_from(
+        &mut self,
+        spender: Principal,
+        from: Principal,
+        to: Principal,
+        amount: u128,
+        memo: Option<Vec<u8>>,
+    ) -> Result<(), String> {
+        self.require_operation(TxKind::TransferFrom)?;
+        self.throttle(spender)?;
+        self.check_memo(to, &memo)?;
+        // A sender-paid fee is drawn from the allowance along with the amount
+        let fee = self.transfer_from_fee();
+        let allowance = self.allowance(from, spender);
+        let (spent, _) = self.fee_split(amount, fee)?;
+        if allowance < spent {
+            return Err("Insufficient allowance".to_string());
+        }
+        // apply_transfer runs its own checks before any write, and the
+        // allowance is only reduced once the balance move has succeeded
+        self.apply_transfer(from, to, amount, fee, memo, TxKind::TransferFrom)?;
+        self.allowances
+            .entry(from)
+            .or_default()
+            .insert(spender, allowance - spent);
+        Ok(())
+    }
+
+    fn check_memo(&self, to: Principal, memo: &Option<Vec<u8>>) -> Result<(), String> {
+        if self.require_memo && memo.as_ref().is_none_or(|m| m.is_empty()) {
+            return Err("Memo required".to_string());
+        }
+        let memo = memo.as_ref().filter(|m| !m.is_empty());
+        if let (Some(window), Some(memo)) = (self.memo_uniqueness_window_ns, memo) {
+            if self.memo_seen(to, memo, window) {
+                return Err("Duplicate memo".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    // Walks the recipient's index newest first and stops at the window edge,
+    // so the cost is bounded by recent activity rather than all history
+    fn memo_seen(&self, to: Principal, memo: &[u8], window_ns: u64) -> bool {
+        let cutoff = env::time().saturating_sub(window_ns);
+        let Some(indices) = self.account_index.get(&to) else {
+            return false;
+        };
+        indices
+            .iter()
+            .rev()
+            .filter_map(|i| self.transaction_history.get(*i as usize))
+            .take_while(|r| r.timestamp >= cutoff)
+            .any(|r| {
+                r.to == to
+                    && matches!(r.kind, TxKind::Transfer | TxKind::TransferFrom)
+                    && r.memo.as_deref() == Some(memo)
+            })
+    }
+
+    pub fn memo_uniqueness_window(&self) -> Option<u64> {
+        self.memo_uniqueness_window_ns
+    }
+
+    pub fn set_memo_uniqueness_window(&mut self, window_ns: Option<u64>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can change memo uniqueness".to_string());
+        }
+        self.memo_uniqueness_window_ns = window_ns;
+        Ok(())
+    }
+
+    pub fn is_exempt(&self, account: Principal) -> bool {
+        account == self.owner || self.exempt_accounts.contains(&account)
+    }
+
+    pub fn set_exempt(&mut self, account: Principal, exempt: bool) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can manage exempt accounts".to_string());
+        }
+        if exempt {
+            self.exempt_accounts.insert(account);
+        } else {
+            self.exempt_accounts.remove(&account);
+        }
+        Ok(())
+    }
+
+    pub fn require_recipient_whitelist(&self) -> bool {
+        self.require_recipient_whitelist
+    }
+
+    pub fn set_require_recipient_whitelist(&mut self, required: bool) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can change the recipient whitelist requirement".to_string());
+        }
+        self.require_recipient_whitelist = required;
+        Ok(())
+    }
+
+    pub fn is_whitelisted_recipient(&self, account: Principal) -> bool {
+        self.recipient_whitelist.contains(&account)
+    }
+
+    pub fn set_whitelisted_recipient(&mut self, account: Principal, whitelisted: bool) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can manage the recipient whitelist".to_string());
+        }
+        if whitelisted {
+            self.recipient_whitelist.insert(account);
+        } else {
+            self.recipient_whitelist.remove(&account);
+        }
+        Ok(())
+    }
+
+    pub fn trading_enabled(&self) -> bool {
+        self.trading_enabled
+    }
+
+    pub fn set_trading_enabled(&mut self, enabled: bool) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can enable trading".to_string());
+        }
+        self.trading_enabled = enabled;
+        Ok(())
+    }
+
+    pub fn is_trading_whitelisted(&self, account: Principal) -> bool {
+        self.trading_whitelist.contains(&account)
+    }
+
+    pub fn set_trading_whitelisted(&mut self, account: Principal, whitelisted: bool) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can manage the trading whitelist".to_string());
+        }
+        if whitelisted {
+            self.trading_whitelist.insert(account);
+        } else {
+            self.trading_whitelist.remove(&account);
+        }
+        Ok(())
+    }
+
+    fn check_wallet_cap(&self, to: Principal, incoming: u128) -> Result<(), String> {
+        if let Some(cap) = self.max_wallet {
+            let exempt = self.is_exempt(to) || to == self.fee_collector;
+            if !exempt && self.balance_of(to).saturating_add(incoming) > cap {
+                return Err("Exceeds max wallet size".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn max_wallet(&self) -> Option<u128> {
+        self.max_wallet
+    }
+
+    pub fn set_max_wallet(&mut self, cap: Option<u128>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the max wallet size".to_string());
+        }
+        self.max_wallet = cap;
+        Ok(())
+    }
+
+    pub fn max_tx_amount(&self) -> Option<u128> {
+        self.max_tx_amount
+    }
+
+    pub fn set_max_tx_amount(&mut self, cap: Option<u128>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the max transaction size".to_string());
+        }
+        self.max_tx_amount = cap;
+        Ok(())
+    }
+
+    pub fn require_memo(&self) -> bool {
+        self.require_memo
+    }
+
+    pub fn set_require_memo(&mut self, required: bool) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can change the memo requirement".to_string());
+        }
+        self.require_memo = required;
+        Ok(())
+    }
+
+    // Every precondition of a balance move, checked without touching state.
+    // Returns the total debit (amount plus fee) for the sender.
+    fn validate_transfer(&self, from: Principal, to: Principal, amount: u128, fee: u128) -> Result<u128, String> {
+        if to == env::canister_id() && !self.accept_direct_deposits {
+            return Err("Cannot transfer to ledger; use deposit".to_string());
+        }
+        if self.require_recipient_whitelist && !self.is_whitelisted_recipient(to) {
+            return Err("Recipient is not whitelisted".to_string());
+        }
+        if !self.trading_enabled && from != self.owner && !self.is_trading_whitelisted(from) {
+            return Err("Trading is not enabled yet".to_string());
+        }
+        self.check_send_lock(from)?;
+        if let Some(cap) = self.max_tx_amount {
+            if amount > cap && !self.is_exempt(from) {
+                return Err("Exceeds max transaction size".to_string());
+            }
+        }
+        let (debit, credit) = self.fee_split(amount, fee)?;
+        if self.spendable_balance(from) < debit {
+            return Err("Insufficient balance".to_string());
+        }
+        if from != to {
+            self.check_wallet_cap(to, credit)?;
+        }
+        Ok(debit)
+    }
+
+    // Validates first and only then mutates, so a rejection leaves no partial
+    // state. Returns what the recipient was credited and the fee charged.
+    fn apply_transfer(
+        &mut self,
+        from: Principal,
+        to: Principal,
+        amount: u128,
+        fee: u128,
+        memo: Option<Vec<u8>>,
+        kind: TxKind,
+    ) -> Result<(u128, u128), String> {
+        let debit = self.validate_transfer(from, to, amount, fee)?;
+        // Records always hold what the recipient got and the fee on top, so
+        // a recipient-paid fee shows as a smaller amount
+        let (_, credit) = self.fee_split(amount, fee)?;
+        self.clear_expired_lock(from);
+        *self.balances.entry(from).or_insert(0) -= debit;
+        *self.balances.entry(to).or_insert(0) += credit;
+        if fee > 0 {
+            *self.balances.entry(self.fee_collector).or_insert(0) += fee;
+        }
+        let reason = match (fee > 0, self.fee_payer) {
+            (false, _) => "No transfer fee applied.".to_string(),
+            (true, FeePayer::Sender) => "Transfer fee was paid to the fee collector.".to_string(),
+            (true, FeePayer::Recipient) => "Transfer fee was deducted from the amount sent.".to_string(),
+        };
+
+        // Log the transaction
+        let record = TransactionRecord {
+            from,
+            to,
+            amount: credit,
+            post_balance_from: self.balances.get(&from).copied().unwrap_or(0),
+            post_balance_to: self.balances.get(&to).copied().unwrap_or(0),
+            cycles_burnt: 0,
+            reason,
+            kind,
+            timestamp: env::time(),
+            memo,
+            fee,
+            caller: Some(env::caller()),
+            authorized_by: Some(from),
+        };
+        self.push_record(record);
+        self.reap_dust(from);
+
+        Ok((credit, fee))
+    }
+
+    // The transfer half of transfer_and_notify: sends `amount` and holds what
+    // the receiver was credited. The hold is checked before anything moves,
+    // so an error leaves balances as they were.
+    pub fn transfer_and_hold(&mut self, from: Principal, to: Principal, amount: u128) -> Result<u128, String> {
+        // A queued transfer would leave nothing to hold or notify about
+        self.require_operation(TxKind::Transfer)?;
+        let fee = self.transfer_fee(amount);
+        let debit = self.validate_transfer(from, to, amount, fee)?;
+        let received = self.received_amount(amount);
+        let mut balance_after = self.balance_of(to).saturating_add(received);
+        if from == to {
+            balance_after -= debit;
+        }
+        if balance_after.saturating_sub(self.locked_balance(to)) < received {
+            return Err("Receiver cannot hold the amount received".to_string());
+        }
+        self.transfer(from, to, amount, None)?;
+        self.hold(to, received)?;
+        Ok(received)
+    }
+
+    // Undoes a notified transfer whose receiver rejected the callback. The
+    // amount was held in the receiver's account for the duration of the call.
+    // Only that amount comes back: the transfer did happen, so its fee stays
+    // with the fee collector.
+    pub fn rollback_transfer(&mut self, from: Principal, to: Principal, amount: u128) -> Result<(), String> {
+        self.release_hold(to, amount)?;
+        *self.balances.entry(to).or_insert(0) -= amount;
+        *self.balances.entry(from).or_insert(0) += amount;
+        let record = TransactionRecord {
+            from: to,
+            to: from,
+            amount,
+            post_balance_from: self.balance_of(to),
+            post_balance_to: self.balance_of(from),
+            cycles_burnt: 0,
+            reason: "Transfer rolled back after the receiver rejected the notification.".to_string(),
+            kind: TxKind::Rollback,
+            timestamp: env::time(),
+            memo: None,
+            fee: 0,
+            caller: Some(env::caller()),
+            authorized_by: None,
+        };
+        self.push_record(record);
+        Ok(())
+    }
+
+    pub fn fee_collector(&self) -> Principal {
+        self.fee_collector
+    }
+
+    pub fn set_fee_collector(&mut self, collector: Principal) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the fee collector".to_string());
+        }
+        self.fee_collector = collector;
+        Ok(())
+    }
+
+    pub fn accept_direct_deposits(&self) -> bool {
+        self.accept_direct_deposits
+    }
+
+    pub fn set_accept_direct_deposits(&mut self, accept: bool) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can change direct deposit handling".to_string());
+        }
+        self.accept_direct_deposits = accept;
+        Ok(())
+    }
+
+    pub fn existential_deposit(&self) -> u128 {
+        self.existential_deposit
+    }
+
+    pub fn set_existential_deposit(&mut self, deposit: u128) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the existential deposit".to_string());
+        }
+        self.existential_deposit = deposit;
+        Ok(())
+    }
+
+    // Sweeps a leftover balance below the existential deposit to the fee
+    // collector and drops the account. Accounts with escrowed funds are kept.
+    fn reap_dust(&mut self, account: Principal) {
+        let remainder = self.balance_of(account);
+        if remainder == 0
+            || remainder >= self.existential_deposit
+            || self.locked_balance(account) > 0
+            || account == self.fee_collector
+        {
+            return;
+        }
+        self.balances.remove(&account);
+        let collector = self.fee_collector;
+        *self.balances.entry(collector).or_insert(0) += remainder;
+        let record = TransactionRecord {
+            from: account,
+            to: collector,
+            amount: remainder,
+            post_balance_from: 0,
+            post_balance_to: self.balance_of(collector),
+            cycles_burnt: 0,
+            reason: "Balance below existential deposit was reaped.".to_string(),
+            kind: TxKind::Reap,
+            timestamp: env::time(),
+            memo: None,
+            fee: 0,
+            caller: Some(env::caller()),
+            authorized_by: None,
+        };
+        self.push_record(record);
+    }
+
+    // Timestamp of the newest record involving `account`, from the account index
+    pub fn last_activity(&self, account: Principal) -> Option<u64> {
+        let index = *self.account_index.get(&account)?.last()?;
+        self.transaction_history.get(index as usize).map(|r| r.timestamp)
+    }
+
+    pub fn inactivity_period(&self) -> Option<u64> {
+        self.inactivity_period_ns
+    }
+
+    pub fn set_inactivity_period(&mut self, period_ns: Option<u64>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the inactivity period".to_string());
+        }
+        if period_ns.is_some_and(|p| p < MIN_INACTIVITY_PERIOD_NS) {
+            return Err("Inactivity period must be at least one year".to_string());
+        }
+        self.inactivity_period_ns = period_ns;
+        Ok(())
+    }
+
+    // Records from before timestamps were kept carry 0, which proves nothing
+    fn check_inactive(&self, account: Principal) -> Result<(), String> {
+        if account == env::canister_id() {
+            return Err("The ledger's own account holds the swap pool".to_string());
+        }
+        let period = self.inactivity_period_ns.ok_or("Reclamation is disabled")?;
+        let last = self.last_activity(account).filter(|t| *t > 0).ok_or("No provable activity history")?;
+        if env::time().saturating_sub(last) < period {
+            return Err("Account was active within the inactivity period".to_string());
+        }
+        if self.spendable_balance(account) == 0 {
+            return Err("Nothing to reclaim".to_string());
+        }
+        Ok(())
+    }
+
+    // First step of a reclamation; reclaim_inactive must repeat the same destination
+    pub fn propose_reclaim(&mut self, account: Principal, to: Principal) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can reclaim inactive accounts".to_string());
+        }
+        self.check_inactive(account)?;
+        self.pending_reclaims.insert(account, to);
+        Ok(())
+    }
+
+    // Moves the spendable balance, matured locks included, and returns the
+    // amount. Held, staked and still-locked tokens stay with the account.
+    // Activity since the proposal voids it.
+    pub fn reclaim_inactive(&mut self, account: Principal, to: Principal) -> Result<u128, String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can reclaim inactive accounts".to_string());
+        }
+        if self.pending_reclaims.get(&account) != Some(&to) {
+            return Err("No matching reclaim proposal".to_string());
+        }
+        if let Err(e) = self.check_inactive(account) {
+            self.pending_reclaims.remove(&account);
+            return Err(e);
+        }
+        self.pending_reclaims.remove(&account);
+        let amount = self.spendable_balance(account);
+        let now = env::time();
+        if let Some(locks) = self.time_locks.get_mut(&account) {
+            locks.retain(|l| l.unlock_at > now);
+            if locks.is_empty() {
+                self.time_locks.remove(&account);
+            }
+        }
+        *self.balances.entry(account).or_insert(0) -= amount;
+        if self.balance_of(account) == 0 {
+            self.balances.remove(&account);
+        }
+        *self.balances.entry(to).or_insert(0) += amount;
+        let record = TransactionRecord {
+            from: account,
+            to,
+            amount,
+            post_balance_from: self.balance_of(account),
+            post_balance_to: self.balance_of(to),
+            cycles_burnt: 0,
+            reason: "Balance of an inactive account was reclaimed.".to_string(),
+            kind: TxKind::Reclaim,
+            timestamp: env::time(),
+            memo: None,
+            fee: 0,
+            caller: Some(env::caller()),
+            authorized_by: Some(self.owner),
+        };
+        self.push_record(record);
+        Ok(amount)
+    }
+
+    // Court-ordered move needing no allowance. Send locks, limits and
+    // whitelists don't apply, but locked, staked and held tokens stay put.
+    // The stated reason is kept in the record.
+    pub fn force_transfer(&mut self, from: Principal, to: Principal, amount: u128, reason: String) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can force a transfer".to_string());
+        }
+        self.require_operation(TxKind::ForceTransfer)?;
+        if reason.trim().is_empty() {
+            return Err("A forced transfer needs a reason".to_string());
+        }
+        if amount == 0 || from == to {
+            return Err("Forced transfer must move a nonzero amount between two accounts".to_string());
+        }
+        if self.spendable_balance(from) < amount {
+            return Err("Insufficient balance".to_string());
+        }
+        *self.balances.entry(from).or_insert(0) -= amount;
+        *self.balances.entry(to).or_insert(0) += amount;
+        let record = TransactionRecord {
+            from,
+            to,
+            amount,
+            post_balance_from: self.balance_of(from),
+            post_balance_to: self.balance_of(to),
+            cycles_burnt: 0,
+            reason: format!("Forced transfer: {}", reason),
+            kind: TxKind::ForceTransfer,
+            timestamp: env::time(),
+            memo: None,
+            fee: 0,
+            caller: Some(env::caller()),
+            authorized_by: Some(self.owner),
+        };
+        self.push_record(record);
+        Ok(())
+    }
+
+    // Returns the allowance now in force, sparing clients a follow-up query
+    pub fn approve(
+        &mut self,
+        owner: Principal,
+        spender: Principal,
+        amount: u128,
+        expires_at: Option<u64>,
+        expected_fee: Option<u128>,
+    ) -> Result<u128, String> {
+        self.require_operation(TxKind::Approve)?;
+        self.throttle(owner)?;
+        if expires_at.is_some_and(|e| e <= env::time()) {
+            return Err("Approval already expired".to_string());
+        }
+        if amount > 0 && amount < self.min_approval_amount {
+            return Err(format!("Approval is below the minimum of {}", self.min_approval_amount));
+        }
+        let fee = self.approve_fee;
+        if expected_fee.is_some_and(|f| f != fee) {
+            return Err(format!("Bad fee: expected {}", fee));
+        }
+        if self.spendable_balance(owner) < fee {
+            return Err("Insufficient balance for approve fee".to_string());
+        }
+        if let Some(ceiling) = self.max_allowance {
+            let granted_by_others =
+                self.total_allowance_for_spender(spender) - self.allowance(owner, spender);
+            if granted_by_others.saturating_add(amount) > ceiling {
+                return Err("Allowance exceeds spender ceiling".to_string());
+            }
+        }
+        self.allowances
+            .entry(owner)
+            .or_default()
+            .insert(spender, amount);
+        match expires_at {
+            Some(e) => self.allowance_expiries.insert((owner, spender), e),
+            None => self.allowance_expiries.remove(&(owner, spender)),
+        };
+        if fee > 0 {
+            *self.balances.entry(owner).or_insert(0) -= fee;
+            *self.balances.entry(self.fee_collector).or_insert(0) += fee;
+        }
+        let record = TransactionRecord {
+            from: owner,
+            to: spender,
+            amount,
+            post_balance_from: self.balance_of(owner),
+            post_balance_to: self.balance_of(spender),
+            cycles_burnt: 0,
+            reason: "Allowance set; amount is the approved allowance, not a balance move.".to_string(),
+            kind: TxKind::Approve,
+            timestamp: env::time(),
+            memo: None,
+            fee,
+            caller: Some(env::caller()),
+            authorized_by: Some(owner),
+        };
+        self.push_record(record);
+        Ok(amount)
+    }
+
+    // Moves owner's approval, amount and expiry, to a new spender in one step,
+    // e.g. when a protocol migrates canisters. Logged as two Approve records.
+    pub fn reassign_allowance(
+        &mut self,
+        owner: Principal,
+        old_spender: Principal,
+        new_spender: Principal,
+    ) -> Result<u128, String> {
+        self.require_operation(TxKind::Approve)?;
+        self.throttle(owner)?;
+        if old_spender == new_spender {
+            return Err("New spender is the current spender".to_string());
+        }
+        let amount = self.allowance(owner, old_spender);
+        if amount == 0 {
+            return Err("No allowance to reassign".to_string());
+        }
+        if self.allowance(owner, new_spender) > 0 {
+            return Err("New spender already has an allowance".to_string());
+        }
+        if let Some(ceiling) = self.max_allowance {
+            if self.total_allowance_for_spender(new_spender).saturating_add(amount) > ceiling {
+                return Err("Allowance exceeds spender ceiling".to_string());
+            }
+        }
+        let expiry = self.allowance_expiries.remove(&(owner, old_spender));
+        let spenders = self.allowances.entry(owner).or_default();
+        spenders.remove(&old_spender);
+        spenders.insert(new_spender, amount);
+        match expiry {
+            Some(e) => self.allowance_expiries.insert((owner, new_spender), e),
+            None => self.allowance_expiries.remove(&(owner, new_spender)),
+        };
+        for (spender, approved) in [(old_spender, 0), (new_spender, amount)] {
+            let record = TransactionRecord {
+                from: owner,
+                to: spender,
+                amount: approved,
+                post_balance_from: self.balance_of(owner),
+                post_balance_to: self.balance_of(spender),
+                cycles_burnt: 0,
+                reason: "Allowance reassigned to a new spender.".to_string(),
+                kind: TxKind::Approve,
+                timestamp: env::time(),
+                memo: None,
+                fee: 0,
+                caller: Some(env::caller()),
+                authorized_by: Some(owner),
+            };
+            self.push_record(record);
+        }
+        Ok(amount)
+    }
+
+    // Incident response: the ledger owner zeroes someone else's approval
+    pub fn force_revoke_allowance(&mut self, owner: Principal, spender: Principal) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can force-revoke allowances".to_string());
+        }
+        if !self.revoke_allowance(owner, spender, "Allowance force-revoked by the ledger owner.", self.owner) {
+            return Err("No allowance to revoke".to_string());
+        }
+        Ok(())
+    }
+
+    // Cuts a compromised spender off from every owner's approvals at once.
+    // Each revoked approval gets its own record; returns how many there were.
+    pub fn force_revoke_spender(&mut self, spender: Principal) -> Result<u64, String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can force-revoke allowances".to_string());
+        }
+        let mut owners: Vec<Principal> = self
+            .allowances
+            .iter()
+            .filter(|(_, spenders)| spenders.contains_key(&spender))
+            .map(|(owner, _)| *owner)
+            .collect();
+        owners.sort();
+        let mut revoked = 0;
+        for owner in owners {
+            if self.revoke_allowance(owner, spender, "Allowance force-revoked by the ledger owner.", self.owner) {
+                revoked += 1;
+            }
+        }
+        Ok(revoked)
+    }
+
+    // For a spender giving up an approval it no longer needs
+    pub fn renounce_allowance(&mut self, owner: Principal) -> Result<(), String> {
+        let spender = env::caller();
+        if !self.revoke_allowance(owner, spender, "Allowance renounced by the spender.", spender) {
+            return Err("No allowance to renounce".to_string());
+        }
+        Ok(())
+    }
+
+    // Drops the approval and logs a zero-amount Approve record; false if there was none
+    fn revoke_allowance(&mut self, owner: Principal, spender: Principal, reason: &str, authorized_by: Principal) -> bool {
+        let removed = self
+            .allowances
+            .get_mut(&owner)
+            .and_then(|spenders| spenders.remove(&spender))
+            .is_some();
+        if self.allowances.get(&owner).is_some_and(|spenders| spenders.is_empty()) {
+            self.allowances.remove(&owner);
+        }
+        self.allowance_expiries.remove(&(owner, spender));
+        if !removed {
+            return false;
+        }
+        let record = TransactionRecord {
+            from: owner,
+            to: spender,
+            amount: 0,
+            post_balance_from: self.balance_of(owner),
+            post_balance_to: self.balance_of(spender),
+            cycles_burnt: 0,
+            reason: reason.to_string(),
+            kind: TxKind::Approve,
+            timestamp: env::time(),
+            memo: None,
+            fee: 0,
+            caller: Some(env::caller()),
+            authorized_by: Some(authorized_by),
+        };
+        self.push_record(record);
+        true
+    }
+
+    pub fn approve_fee(&self) -> u128 {
+        self.approve_fee
+    }
+
+    pub fn set_approve_fee(&mut self, fee: u128) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the approve fee".to_string());
+        }
+        self.approve_fee = fee;
+        Ok(())
+    }
+
+    pub fn min_approval_amount(&self) -> u128 {
+        self.min_approval_amount
+    }
+
+    // Zero approvals stay allowed whatever the minimum, so revoking always works
+    pub fn set_min_approval_amount(&mut self, amount: u128) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the minimum approval".to_string());
+        }
+        self.min_approval_amount = amount;
+        Ok(())
+    }
+
+    // Drops every expired approval, matured time lock, lapsed send lock and
+    // stale mint proposal. Tokens never leave balances while locked, so
+    // dropping a matured lock is all it takes to make them spendable again.
+    pub fn reap_expired(&mut self) -> ReapSummary {
+        let now = env::time();
+        let mut summary = ReapSummary::default();
+
+        let expired: Vec<(Principal, Principal)> = self
+            .allowance_expiries
+            .iter()
+            .filter(|(_, e)| **e <= now)
+            .map(|(key, _)| *key)
+            .collect();
+        for (owner, spender) in expired {
+            self.allowance_expiries.remove(&(owner, spender));
+            if let Some(spenders) = self.allowances.get_mut(&owner) {
+                spenders.remove(&spender);
+                if spenders.is_empty() {
+                    self.allowances.remove(&owner);
+                }
+            }
+            summary.allowances += 1;
+        }
+
+        self.time_locks.retain(|_, locks| {
+            let before = locks.len();
+            locks.retain(|l| l.unlock_at > now);
+            summary.time_locks += (before - locks.len()) as u64;
+            !locks.is_empty()
+        });
+
+        let before = self.locked_until.len();
+        self.locked_until.retain(|_, until| *until > now);
+        summary.account_locks = (before - self.locked_until.len()) as u64;
+
+        let before = self.mint_proposals.len();
+        self.mint_proposals.retain(|_, p| p.expires_at > now);
+        summary.mint_proposals = (before - self.mint_proposals.len()) as u64;
+
+        summary.stakes_compounded = self.compound_stakes();
+        summary.deflation_burned = self.run_deflation();
+
+        summary
+    }
+
+    // Live approvals whose expiry falls within the next `within_ns`
+    pub fn expiring_allowances(&self, within_ns: u64) -> Vec<(Principal, Principal, u64)> {
+        let now = env::time();
+        let horizon = now.saturating_add(within_ns);
+        let mut expiring: Vec<(Principal, Principal, u64)> = self
+            .allowance_expiries
+            .iter()
+            .filter(|(_, e)| **e > now && **e <= horizon)
+            .filter(|((owner, spender), _)| self.allowance(*owner, *spender) > 0)
+            .map(|((owner, spender), e)| (*owner, *spender, *e))
+            .collect();
+        expiring.sort_by_key(|(_, _, e)| *e);
+        expiring
+    }
+
+    // Sum of what every owner has approved for `spender`. Walks all owners'
+    // allowance maps, so cost grows with the number of approving accounts.
+    pub fn total_allowance_for_spender(&self, spender: Principal) -> u128 {
+        self.allowances
+            .keys()
+            .map(|owner| self.allowance(*owner, spender))
+            .fold(0u128, |acc, a| acc.saturating_add(a))
+    }
+
+    pub fn max_allowance(&self) -> Option<u128> {
+        self.max_allowance
+    }
+
+    pub fn set_max_allowance(&mut self, ceiling: Option<u128>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the allowance ceiling".to_string());
+        }
+        self.max_allowance = ceiling;
+        Ok(())
+    }
+
+    pub fn burn_cycles(&mut self, cycles: u64) {
+        self.burnt_cycles += cycles;
+    }
+
+    pub fn burnt_cycles(&self) -> u64 {
+        self.burnt_cycles
+    }
+
+    // Metered cycles summed per record kind, for the kinds present, in tag
+    // order. Cycles reported through burn_cycles belong to no record, so
+    // these can add up to less than burnt_cycles.
+    pub fn cycles_by_operation(&self) -> Vec<(TxKind, u64)> {
+        let mut totals: Vec<(TxKind, u64)> = Vec::new();
+        for record in &self.transaction_history {
+            match totals.iter_mut().find(|(kind, _)| *kind == record.kind) {
+                Some((_, total)) => *total = total.saturating_add(record.cycles_burnt),
+                None => totals.push((record.kind, record.cycles_burnt)),
+            }
+        }
+        totals.sort_by_key(|(kind, _)| kind.tag());
+        totals
+    }
+
+    pub fn add_minter(&mut self, minter: Principal) -> Result<(), String> {
+        let owner = env::caller();
+        if owner != self.get_owner() {
+            return Err("Only the owner can add minters".to_string());
+        }
+        if let Some(cap) = self.max_minters {
+            if !self.minters.contains(&minter) && self.minters.len() >= cap as usize {
+                return Err("Minter limit reached".to_string());
+            }
+        }
+        if self.minters.insert(minter) {
+            self.minter_granted_at.insert(minter, env::time());
+        }
+        Ok(())
+    }
+
+    // Minters whose grant predates tracking count as long since active
+    fn require_minter(&self, caller: Principal) -> Result<(), String> {
+        if !self.minters.contains(&caller) {
+            return Err("Caller is not authorized to mint".to_string());
+        }
+        if caller == self.owner {
+            return Ok(());
+        }
+        let Some(granted_at) = self.minter_granted_at.get(&caller) else {
+            return Ok(());
+        };
+        let active_at = granted_at.saturating_add(self.minter_activation_delay_ns);
+        if env::time() < active_at {
+            return Err(format!("Minter is not active until {}", active_at));
+        }
+        Ok(())
+    }
+
+    pub fn minter_activation_delay(&self) -> u64 {
+        self.minter_activation_delay_ns
+    }
+
+    // Applies to minters already waiting, measured from their original grant
+    pub fn set_minter_activation_delay(&mut self, delay_ns: u64) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the minter activation delay".to_string());
+        }
+        self.minter_activation_delay_ns = delay_ns;
+        Ok(())
+    }
+
+    pub fn max_minters(&self) -> Option<u32> {
+        self.max_minters
+    }
+
+    pub fn set_max_minters(&mut self, cap: Option<u32>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the minter limit".to_string());
+        }
+        if cap.is_some_and(|c| (c as usize) < self.minters.len()) {
+            return Err("Minter limit is below the current minter count".to_string());
+        }
+        self.max_minters = cap;
+        Ok(())
+    }
+
+    pub fn mint(&mut self, to: Principal, amount: u128, custody_ref: Option<String>) -> Result<(), String> {
+        let caller = env::caller();
+        self.require_minter(caller)?;
+        self.throttle(caller)?;
+        if self.mint_threshold > 1 {
+            return Err("Minting requires a multisig proposal".to_string());
+        }
+        self.execute_mint(caller, to, amount, custody_ref).map(|_| ())
+    }
+
+    // Mints `total_amount` across current holders in proportion to their
+    // balances. The mint fee comes off the top as usual; the rounding dust
+    // goes to the fee collector along with it. Every share is checked before
+    // anything is credited. The user registry isn't consulted: it screens new
+    // mint recipients, and holders already hold. Returns the number credited.
+    pub fn distribute_pro_rata(&mut self, total_amount: u128, custody_ref: Option<String>) -> Result<u64, String> {
+        let caller = env::caller();
+        self.require_minter(caller)?;
+        self.throttle(caller)?;
+        if self.mint_threshold > 1 {
+            return Err("Minting requires a multisig proposal".to_string());
+        }
+        self.require_operation(TxKind::Mint)?;
+        self.check_custody_ref(&custody_ref)?;
+        // The pool's reserve and the burn account aren't holders
+        let mut holders: Vec<(Principal, u128)> = self
+            .balances
+            .iter()
+            .filter(|(p, b)| **b > 0 && **p != env::canister_id() && **p != self.burn_account)
+            .map(|(p, b)| (*p, *b))
+            .collect();
+        holders.sort_by_key(|(p, _)| *p);
+        if holders.is_empty() {
+            return Err("No holders to distribute to".to_string());
+        }
+        if holders.len() > DISTRIBUTION_MAX_HOLDERS {
+            return Err(format!("Distribution is limited to {} holders", DISTRIBUTION_MAX_HOLDERS));
+        }
+        let held_total = holders.iter().fold(0u128, |sum, (_, b)| sum.saturating_add(*b));
+        let distributable = total_amount - self.bps_portion(total_amount, self.mint_fee_bps);
+        let mut shares = Vec::with_capacity(holders.len());
+        for (holder, balance) in holders {
+            let share = amm::mul_div(distributable, balance, held_total).ok_or("Distribution overflow")?;
+            if share == 0 {
+                continue;
+            }
+            self.check_wallet_cap(holder, share)?;
+            shares.push((holder, share));
+        }
+        let total_supply = self.total_supply.checked_add(total_amount).ok_or("Supply overflow")?;
+        let credited = shares.iter().fold(0u128, |sum, (_, s)| sum + s);
+        self.total_supply = total_supply;
+        let memo = custody_ref.map(String::into_bytes);
+        for (holder, share) in &shares {
+            self.credit_mint(caller, *holder, *share, "Pro-rata distribution credited new supply.", memo.clone());
+        }
+        if total_amount > credited {
+            let collector = self.fee_collector;
+            let reason = "Mint fee and distribution rounding credited to the fee collector.";
+            self.credit_mint(caller, collector, total_amount - credited, reason, memo);
+        }
+        Ok(shares.len() as u64)
+    }
+
+    // Supply grows by the full amount; the mint fee share goes to the fee
+    // collector as its own Mint record. Both records carry the custody
+    // reference as their memo. Returns what `to` was credited.
+    fn execute_mint(
+        &mut self,
+        caller: Principal,
+        to: Principal,
+        amount: u128,
+        custody_ref: Option<String>,
+    ) -> Result<u128, String> {
+        self.require_operation(TxKind::Mint)?;
+        self.check_custody_ref(&custody_ref)?;
+        if self.user_registry.is_some() && !self.registration_fresh(to) {
+            return Err("Recipient is not registered".to_string());
+        }
+        let treasury_cut = self.bps_portion(amount, self.mint_fee_bps);
+        let credited = amount - treasury_cut;
+        self.check_wallet_cap(to, credited)?;
+        self.total_supply = self.total_supply.checked_add(amount).ok_or("Supply overflow")?;
+        let memo = custody_ref.map(String::into_bytes);
+        self.credit_mint(caller, to, credited, "Minting operation credited new supply.", memo.clone());
+        if treasury_cut > 0 {
+            let collector = self.fee_collector;
+            self.credit_mint(caller, collector, treasury_cut, "Mint fee credited to the fee collector.", memo);
+        }
+        Ok(credited)
+    }
+
+    fn check_custody_ref(&self, custody_ref: &Option<String>) -> Result<(), String> {
+        if self.require_custody_ref && custody_ref.as_ref().is_none_or(|r| r.is_empty()) {
+            return Err("Custody reference required".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn require_custody_ref(&self) -> bool {
+        self.require_custody_ref
+    }
+
+    pub fn set_require_custody_ref(&mut self, required: bool) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can change the custody reference requirement".to_string());
+        }
+        self.require_custody_ref = required;
+        Ok(())
+    }
+
+    fn credit_mint(&mut self, caller: Principal, to: Principal, amount: u128, reason: &str, memo: Option<Vec<u8>>) {
+        *self.balances.entry(to).or_insert(0) += amount;
+        let record = TransactionRecord {
+            from: caller,
+            to,
+            amount,
+            post_balance_from: 0,
+            post_balance_to: self.balances.get(&to).copied().unwrap_or(0),
+            cycles_burnt: 0,
+            reason: reason.to_string(),
+            kind: TxKind::Mint,
+            timestamp: env::time(),
+            memo,
+            fee: 0,
+            caller: Some(env::caller()),
+            authorized_by: Some(caller),
+        };
+        self.push_record(record);
+        self.notify_mint_hook(to, amount);
+    }
+
+    // One-way call made after the record is written: the mint stands whatever
+    // the hook does, and a hook that can't be reached is simply skipped
+    fn notify_mint_hook(&self, to: Principal, amount: u128) {
+        if let Some(hook) = self.mint_hook {
+            env::notify_mint_hook(hook, self.mint_hook_payload(to, amount));
+        }
+    }
+
+    fn mint_hook_payload(&self, to: Principal, amount: u128) -> env::MintHookArgs {
+        (to, amount, self.transaction_history.len() as u64 - 1)
+    }
+
+    pub fn mint_hook(&self) -> Option<Principal> {
+        self.mint_hook
+    }
+
+    pub fn set_mint_hook(&mut self, hook: Option<Principal>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the mint hook".to_string());
+        }
+        self.mint_hook = hook;
+        Ok(())
+    }
+
+    pub fn mint_fee_bps(&self) -> u16 {
+        self.mint_fee_bps
+    }
+
+    pub fn set_mint_fee_bps(&mut self, bps: u16) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the mint fee".to_string());
+        }
+        if bps > 10_000 {
+            return Err("Mint fee cannot exceed 10000 bps".to_string());
+        }
+        self.mint_fee_bps = bps;
+        Ok(())
+    }
+
+    // Mints straight into a time lock, e.g. for investor allocations
+    pub fn mint_locked(
+        &mut self,
+        to: Principal,
+        amount: u128,
+        unlock_at: u64,
+        custody_ref: Option<String>,
+    ) -> Result<(), String> {
+        let caller = env::caller();
+        self.require_minter(caller)?;
+        self.throttle(caller)?;
+        if self.mint_threshold > 1 {
+            return Err("Minting requires a multisig proposal".to_string());
+        }
+        if unlock_at <= env::time() {
+            return Err("Unlock time must be in the future".to_string());
+        }
+        let credited = self.execute_mint(caller, to, amount, custody_ref)?;
+        self.time_locks
+            .entry(to)
+            .or_default()
+            .push(TimeLock { amount: credited, unlock_at });
+        Ok(())
+    }
+
+    pub fn mint_threshold(&self) -> u32 {
+        self.mint_threshold
+    }
+
+    pub fn set_mint_threshold(&mut self, threshold: u32) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the mint threshold".to_string());
+        }
+        if threshold == 0 || threshold as usize > self.minters.len() {
+            return Err("Threshold must be between 1 and the number of minters".to_string());
+        }
+        self.mint_threshold = threshold;
+        Ok(())
+    }
+
+    // The proposer's own approval counts towards the threshold
+    pub fn propose_mint(&mut self, to: Principal, amount: u128, custody_ref: Option<String>) -> Result<u64, String> {
+        let caller = env::caller();
+        self.require_minter(caller)?;
+        self.throttle(caller)?;
+        self.check_custody_ref(&custody_ref)?;
+        let id = self.next_proposal_id;
+        self.next_proposal_id += 1;
+        self.mint_proposals.insert(
+            id,
+            MintProposal {
+                id,
+                to,
+                amount,
+                proposer: caller,
+                approvals: vec![caller],
+                expires_at: env::time().saturating_add(self.mint_proposal_ttl_ns),
+                custody_ref,
+            },
+        );
+        // With a threshold of one the mint runs now; if it fails nothing was proposed
+        if let Err(e) = self.try_execute_proposal(id) {
+            self.mint_proposals.remove(&id);
+            return Err(e);
+        }
+        Ok(id)
+    }
+
+    // Returns true once the proposal has gathered enough approvals and minted
+    pub fn approve_mint(&mut self, proposal_id: u64) -> Result<bool, String> {
+        let caller = env::caller();
+        self.require_minter(caller)?;
+        let now = env::time();
+        let proposal = self
+            .mint_proposals
+            .get_mut(&proposal_id)
+            .ok_or("Unknown mint proposal")?;
+        if proposal.expires_at <= now {
+            self.mint_proposals.remove(&proposal_id);
+            return Err("Mint proposal has expired".to_string());
+        }
+        if proposal.approvals.contains(&caller) {
+            return Err("Caller has already approved this proposal".to_string());
+        }
+        proposal.approvals.push(caller);
+        let executed = self.try_execute_proposal(proposal_id);
+        // A failed mint withdraws this approval, so the minter can approve again once it would succeed
+        if executed.is_err() {
+            if let Some(proposal) = self.mint_proposals.get_mut(&proposal_id) {
+                proposal.approvals.retain(|approver| *approver != caller);
+            }
+        }
+        executed
+    }
+
+    // The proposal is only removed once the mint succeeds; a failed one keeps it and its approvals
+    fn try_execute_proposal(&mut self, proposal_id: u64) -> Result<bool, String> {
+        let Some(proposal) = self
+            .mint_proposals
+            .get(&proposal_id)
+            .filter(|p| p.approvals.len() >= self.mint_threshold as usize)
+            .cloned()
+        else {
+            return Ok(false);
+        };
+        self.execute_mint(proposal.proposer, proposal.to, proposal.amount, proposal.custody_ref)?;
+        self.mint_proposals.remove(&proposal_id);
+        Ok(true)
+    }
+
+    pub fn get_mint_proposal(&self, proposal_id: u64) -> Option<MintProposal> {
+        self.mint_proposals.get(&proposal_id).cloned()
+    }
+
+    pub fn stake(&mut self, account: Principal, amount: u128) -> Result<(), String> {
+        self.throttle(account)?;
+        if amount == 0 {
+            return Err("Stake amount must be nonzero".to_string());
+        }
+        if self.spendable_balance(account) < amount {
+            return Err("Insufficient balance".to_string());
+        }
+        self.settle_stakes();
+        let now = env::time();
+        let stake = self.stakes.entry(account).or_insert(StakeInfo { amount: 0, since: now, accrued: 0, auto_compound: false });
+        stake.amount += amount;
+        Ok(())
+    }
+
+    pub fn unstake(&mut self, account: Principal, amount: u128) -> Result<(), String> {
+        self.throttle(account)?;
+        if self.stakes.get(&account).map_or(0, |s| s.amount) < amount {
+            return Err("Unstake exceeds staked amount".to_string());
+        }
+        self.settle_stakes();
+        let Some(stake) = self.stakes.get_mut(&account) else {
+            return Err("Unstake exceeds staked amount".to_string());
+        };
+        stake.amount -= amount;
+        if stake.amount == 0 && stake.accrued == 0 {
+            self.stakes.remove(&account);
+        }
+        Ok(())
+    }
+
+    pub fn stake_info(&self, account: Principal) -> Option<StakeInfo> {
+        self.stakes.get(&account).copied()
+    }
+
+    // Settled rewards plus what the current stake has earned since `since`
+    pub fn pending_rewards(&self, account: Principal) -> u128 {
+        let total = self.total_staked();
+        self.stakes.get(&account).map_or(0, |s| s.accrued.saturating_add(self.accrual(s, total)))
+    }
+
+    // Mints everything accrued so far to the staker and returns the amount
+    pub fn claim_rewards(&mut self, account: Principal) -> Result<u128, String> {
+        self.require_operation(TxKind::Mint)?;
+        self.throttle(account)?;
+        let pending = self.pending_rewards(account);
+        if pending == 0 {
+            return Err("No rewards to claim".to_string());
+        }
+        // Checked before settling so an overflow leaves the stake untouched
+        let total_supply = self.total_supply.checked_add(pending).ok_or("Supply overflow")?;
+        // Claiming leaves the total staked alone, so the other stakes needn't settle
+        let stake = self.settle_stake(account);
+        let reward = std::mem::take(&mut stake.accrued);
+        if stake.amount == 0 {
+            self.stakes.remove(&account);
+        }
+        self.total_supply = total_supply;
+        self.credit_mint(env::canister_id(), account, reward, "Staking rewards minted on claim.", None);
+        Ok(reward)
+    }
+
+    pub fn set_auto_compound(&mut self, account: Principal, enabled: bool) -> Result<(), String> {
+        let stake = self.stakes.get_mut(&account).ok_or("No stake")?;
+        stake.auto_compound = enabled;
+        Ok(())
+    }
+
+    // Mints each auto-compounding stake's rewards and adds them to the stake.
+    // Skipped while minting is disabled, and per stake when its rewards would
+    // overflow the supply; the rewards keep accruing meanwhile.
+    fn compound_stakes(&mut self) -> u64 {
+        if self.require_operation(TxKind::Mint).is_err() {
+            return 0;
+        }
+        // Restaking grows the total, so every stake settles on the old one first
+        self.settle_stakes();
+        let accounts: Vec<Principal> = self
+            .stakes
+            .iter()
+            .filter(|(_, s)| s.auto_compound)
+            .map(|(account, _)| *account)
+            .collect();
+        let mut compounded = 0;
+        for account in accounts {
+            let Some(stake) = self.stakes.get_mut(&account) else {
+                continue;
+            };
+            let Some(total_supply) = self.total_supply.checked_add(stake.accrued) else {
+                continue;
+            };
+            let reward = std::mem::take(&mut stake.accrued);
+            if reward == 0 {
+                continue;
+            }
+            stake.amount += reward;
+            self.total_supply = total_supply;
+            self.credit_mint(env::canister_id(), account, reward, "Staking rewards restaked.", None);
+            compounded += 1;
+        }
+        compounded
+    }
+
+    pub fn staking_emission(&self) -> u128 {
+        self.staking_emission
+    }
+
+    // Settles every stake at the old emission first, so a change is never retroactive
+    pub fn set_staking_emission(&mut self, per_year: u128) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the staking emission".to_string());
+        }
+        self.settle_stakes();
+        self.staking_emission = per_year;
+        Ok(())
+    }
+
+    pub fn total_staked(&self) -> u128 {
+        self.stakes.values().fold(0u128, |sum, s| sum.saturating_add(s.amount))
+    }
+
+    // The yearly emission over the total staked, in basis points; 0 with
+    // nothing staked. The emission is fixed, so more stake means less each.
+    pub fn current_apy_bps(&self) -> u64 {
+        let staked = self.total_staked();
+        if staked == 0 {
+            return 0;
+        }
+        let apy = amm::mul_div(self.staking_emission, 10_000, staked).unwrap_or(u128::MAX);
+        u64::try_from(apy).unwrap_or(u64::MAX)
+    }
+
+    // The stake's share of the emission since `since`. Only exact while the
+    // total staked hasn't changed since then, which settle_stakes ensures.
+    fn accrual(&self, stake: &StakeInfo, total_staked: u128) -> u128 {
+        if total_staked == 0 {
+            return 0;
+        }
+        let elapsed = env::time().saturating_sub(stake.since) as u128;
+        let emitted = amm::mul_div(self.staking_emission, elapsed, YEAR_NS as u128).unwrap_or(u128::MAX);
+        // stake.amount <= total_staked, so the share always fits
+        amm::mul_div(emitted, stake.amount, total_staked).unwrap_or(0)
+    }
+
+    // Folds rewards earned so far into `accrued` and restarts the clock
+    fn settle_stake(&mut self, account: Principal) -> &mut StakeInfo {
+        let now = env::time();
+        let total = self.total_staked();
+        let earned = self.stakes.get(&account).map_or(0, |s| self.accrual(s, total));
+        let stake = self.stakes.entry(account).or_insert(StakeInfo { amount: 0, since: now, accrued: 0, auto_compound: false });
+        stake.accrued = stake.accrued.saturating_add(earned);
+        stake.since = now;
+        stake
+    }
+
+    // settle_stake for every stake, at the total staked before the change
+    // about to be made to it
+    fn settle_stakes(&mut self) {
+        let now = env::time();
+        let total = self.total_staked();
+        let earned: Vec<(Principal, u128)> = self.stakes.iter().map(|(a, s)| (*a, self.accrual(s, total))).collect();
+        for (account, earned) in earned {
+            if let Some(stake) = self.stakes.get_mut(&account) {
+                stake.accrued = stake.accrued.saturating_add(earned);
+                stake.since = now;
+            }
+        }
+    }
+
+    pub fn pool_info(&self) -> PoolInfo {
+        PoolInfo {
+            token_reserve: self.token_reserve,
+            icp_reserve: self.icp_reserve,
+            total_shares: self.total_lp_shares,
+            fee_bps: self.pool_fee_bps,
+        }
+    }
+
+    pub fn lp_shares(&self, account: Principal) -> u128 {
+        self.lp_shares.get(&account).copied().unwrap_or(0)
+    }
+
+    pub fn icp_owed(&self, account: Principal) -> u128 {
+        self.icp_owed.get(&account).copied().unwrap_or(0)
+    }
+
+    fn pool_is_empty(&self) -> bool {
+        self.total_lp_shares == 0 && self.icp_reserve == 0 && self.icp_owed.is_empty()
+    }
+
+    pub fn set_icp_ledger(&mut self, ledger: Option<Principal>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the ICP ledger".to_string());
+        }
+        if ledger != self.icp_ledger && !self.pool_is_empty() {
+            return Err("Pool still holds liquidity".to_string());
+        }
+        self.icp_ledger = ledger;
+        Ok(())
+    }
+
+    pub fn set_icp_fee(&mut self, fee: u128) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the ICP fee".to_string());
+        }
+        self.icp_fee = fee;
+        Ok(())
+    }
+
+    pub fn set_pool_fee_bps(&mut self, bps: u16) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the pool fee".to_string());
+        }
+        if bps > 10_000 {
+            return Err("Pool fee cannot exceed 10000 bps".to_string());
+        }
+        self.pool_fee_bps = bps;
+        Ok(())
+    }
+
+    fn pool_ledger(&self) -> Result<Principal, String> {
+        self.require_operation(TxKind::Transfer)?;
+        self.icp_ledger.ok_or_else(|| "Swap pool is disabled".to_string())
+    }
+
+    fn check_pool_deposit(&self, from: Principal, amount: u128) -> Result<(), String> {
+        self.check_send_lock(from)?;
+        if self.spendable_balance(from) < amount {
+            return Err("Insufficient balance".to_string());
+        }
+        Ok(())
+    }
+
+    // Token-side movement between an account and the pool, recorded like a
+    // fee-free transfer. Callers check the sender and adjust the reserves.
+    fn pool_move(&mut self, from: Principal, to: Principal, amount: u128, reason: &str) {
+        *self.balances.entry(from).or_insert(0) -= amount;
+        *self.balances.entry(to).or_insert(0) += amount;
+        let record = TransactionRecord {
+            from,
+            to,
+            amount,
+            post_balance_from: self.balance_of(from),
+            post_balance_to: self.balance_of(to),
+            cycles_burnt: 0,
+            reason: reason.to_string(),
+            kind: TxKind::Transfer,
+            timestamp: env::time(),
+            memo: None,
+            fee: 0,
+            caller: Some(env::caller()),
+            authorized_by: Some(from),
+        };
+        self.push_record(record);
+    }
+
+    // First half of add_liquidity: holds the tokens while the ICP is pulled
+    pub fn begin_add_liquidity(&mut self, provider: Principal, token_amount: u128, icp_amount: u128) -> Result<Principal, String> {
+        let ledger = self.pool_ledger()?;
+        self.throttle(provider)?;
+        if token_amount == 0 || icp_amount == 0 {
+            return Err("Both sides of a deposit must be nonzero".to_string());
+        }
+        self.check_pool_deposit(provider, token_amount)?;
+        self.hold(provider, token_amount)?;
+        Ok(ledger)
+    }
+
+    // Second half, once the ICP has arrived. If the deposit can't be taken
+    // after all, the ICP becomes claimable with claim_icp.
+    pub fn finish_add_liquidity(&mut self, provider: Principal, token_amount: u128, icp_amount: u128) -> Result<u128, String> {
+        self.release_hold(provider, token_amount)?;
+        let result = self.deposit_liquidity(provider, token_amount, icp_amount);
+        if result.is_err() {
+            self.owe_icp(provider, icp_amount);
+        }
+        result
+    }
+
+    // Shares are priced on the reserves as they are now, not as they were
+    // when the deposit began
+    fn deposit_liquidity(&mut self, provider: Principal, token_amount: u128, icp_amount: u128) -> Result<u128, String> {
+        let shares = if self.total_lp_shares == 0 {
+            amm::initial_shares(token_amount, icp_amount)
+        } else {
+            amm::deposit_shares(token_amount, icp_amount, self.token_reserve, self.icp_reserve, self.total_lp_shares)
+                .ok_or("Deposit is too large")?
+        };
+        let token_reserve = self.token_reserve.checked_add(token_amount).ok_or("Deposit is too large")?;
+        let icp_reserve = self.icp_reserve.checked_add(icp_amount).ok_or("Deposit is too large")?;
+        let total_shares = self.total_lp_shares.checked_add(shares).ok_or("Deposit is too large")?;
+        if shares == 0 {
+            return Err("Deposit is too small to mint shares".to_string());
+        }
+        self.check_pool_deposit(provider, token_amount)?;
+        self.pool_move(provider, env::canister_id(), token_amount, "Liquidity added to the swap pool.");
+        self.token_reserve = token_reserve;
+        self.icp_reserve = icp_reserve;
+        self.total_lp_shares = total_shares;
+        *self.lp_shares.entry(provider).or_insert(0) += shares;
+        Ok(shares)
+    }
+
+    pub fn abort_add_liquidity(&mut self, provider: Principal, token_amount: u128) -> Result<(), String> {
+        self.release_hold(provider, token_amount)
+    }
+
+    // Burns shares and pays out the token side; returns the ICP to send,
+    // net of the ICP ledger fee
+    pub fn remove_liquidity(&mut self, provider: Principal, shares: u128) -> Result<(Principal, u128, u128), String> {
+        let ledger = self.pool_ledger()?;
+        self.throttle(provider)?;
+        if shares == 0 || self.lp_shares(provider) < shares {
+            return Err("Insufficient pool shares".to_string());
+        }
+        let (token_out, icp_out) =
+            amm::withdrawal(shares, self.token_reserve, self.icp_reserve, self.total_lp_shares).ok_or("Withdrawal overflow")?;
+        if icp_out <= self.icp_fee {
+            return Err("Withdrawal is too small to cover the ICP fee".to_string());
+        }
+        self.token_reserve -= token_out;
+        self.icp_reserve -= icp_out;
+        self.total_lp_shares -= shares;
+        let remaining = self.lp_shares(provider) - shares;
+        if remaining == 0 {
+            self.lp_shares.remove(&provider);
+        } else {
+            self.lp_shares.insert(provider, remaining);
+        }
+        self.pool_move(env::canister_id(), provider, token_out, "Liquidity removed from the swap pool.");
+        Ok((ledger, token_out, icp_out - self.icp_fee))
+    }
+
+    // Sells tokens into the pool; returns the ICP to send, net of the ICP ledger fee
+    pub fn swap_token_for_icp(&mut self, trader: Principal, token_in: u128, min_icp_out: u128) -> Result<(Principal, u128), String> {
+        let ledger = self.pool_ledger()?;
+        self.throttle(trader)?;
+        let icp_out = amm::swap_output(token_in, self.token_reserve, self.icp_reserve, self.pool_fee_bps)
+            .ok_or("Pool has no liquidity")?;
+        if icp_out <= self.icp_fee {
+            return Err("Swap is too small to cover the ICP fee".to_string());
+        }
+        let received = icp_out - self.icp_fee;
+        if received < min_icp_out {
+            return Err("Slippage limit exceeded".to_string());
+        }
+        let token_reserve = self.token_reserve.checked_add(token_in).ok_or("Swap is too large")?;
+        self.check_pool_deposit(trader, token_in)?;
+        self.pool_move(trader, env::canister_id(), token_in, "Tokens swapped for ICP.");
+        self.token_reserve = token_reserve;
+        self.icp_reserve -= icp_out;
+        Ok((ledger, received))
+    }
+
+    // Runs after the trader's ICP reached the pool. If the price moved past
+    // the limit meanwhile, the ICP becomes claimable instead of being swapped.
+    pub fn swap_icp_for_token(&mut self, trader: Principal, icp_in: u128, min_token_out: u128) -> Result<u128, String> {
+        let token_out = amm::swap_output(icp_in, self.icp_reserve, self.token_reserve, self.pool_fee_bps).unwrap_or(0);
+        let icp_reserve = match self.icp_reserve.checked_add(icp_in) {
+            Some(reserve) if token_out > 0 && token_out >= min_token_out => reserve,
+            _ => {
+                self.owe_icp(trader, icp_in);
+                return Err("Slippage limit exceeded; the ICP can be reclaimed with claim_icp".to_string());
+            }
+        };
+        self.icp_reserve = icp_reserve;
+        self.token_reserve -= token_out;
+        self.pool_move(env::canister_id(), trader, token_out, "ICP swapped for tokens.");
+        Ok(token_out)
+    }
+
+    // Quote for swap_icp_for_token, checked before the ICP is pulled
+    pub fn quote_icp_for_token(&self, icp_in: u128) -> Result<(Principal, u128), String> {
+        let ledger = self.pool_ledger()?;
+        let token_out = amm::swap_output(icp_in, self.icp_reserve, self.token_reserve, self.pool_fee_bps)
+            .ok_or("Pool has no liquidity")?;
+        Ok((ledger, token_out))
+    }
+
+    // A payout the ICP ledger refused; kept for claim_icp
+    pub fn owe_icp(&mut self, account: Principal, amount: u128) {
+        let owed = self.icp_owed.entry(account).or_insert(0);
+        *owed = owed.saturating_add(amount);
+    }
+
+    // Takes the whole claim; the caller re-credits it if the payout fails
+    pub fn take_icp_owed(&mut self, account: Principal) -> Result<(Principal, u128), String> {
+        let ledger = self.icp_ledger.ok_or("Swap pool is disabled")?;
+        if self.icp_owed(account) <= self.icp_fee {
+            return Err("Nothing to claim above the ICP fee".to_string());
+        }
+        let owed = self.icp_owed.remove(&account).unwrap_or(0);
+        Ok((ledger, owed))
+    }
+
+    pub fn icp_fee(&self) -> u128 {
+        self.icp_fee
+    }
+
+    // Expired mint proposals still show until they are reaped
+    pub fn pending_operations(&self) -> PendingOps {
+        let mut mint_proposals: Vec<MintProposal> = self.mint_proposals.values().cloned().collect();
+        mint_proposals.sort_by_key(|p| p.id);
+        let mut holds: Vec<(Principal, u128)> = self.held.iter().map(|(p, a)| (*p, *a)).collect();
+        holds.sort();
+        let mut burns: Vec<(Principal, u128)> = self.pending_burns.iter().map(|(p, a)| (*p, *a)).collect();
+        burns.sort();
+        let mut reclaims: Vec<(Principal, Principal)> = self.pending_reclaims.iter().map(|(a, to)| (*a, *to)).collect();
+        reclaims.sort();
+        PendingOps { mint_proposals, holds, burns, reclaims }
+    }
+
+    pub fn burn(&mut self, from: Principal, amount: u128) -> Result<(), String> {
+        self.require_operation(TxKind::Burn)?;
+        self.throttle(from)?;
+        if self.large_burn_threshold.is_some_and(|threshold| amount > threshold) {
+            return Err("Large burn requires propose_burn and confirm_burn".to_string());
+        }
+        self.execute_burn(from, amount)
+    }
+
+    // First step of a large burn; nothing leaves the balance until confirm_burn.
+    // A new proposal replaces any earlier one from the same account.
+    pub fn propose_burn(&mut self, from: Principal, amount: u128) -> Result<(), String> {
+        self.require_operation(TxKind::Burn)?;
+        self.throttle(from)?;
+        if amount == 0 {
+            return Err("Burn amount must be nonzero".to_string());
+        }
+        if self.spendable_balance(from) < amount {
+            return Err("Insufficient balance".to_string());
+        }
+        self.pending_burns.insert(from, amount);
+        Ok(())
+    }
+
+    // Burns the proposed amount; `amount` must repeat it to guard against a stale proposal
+    pub fn confirm_burn(&mut self, from: Principal, amount: u128) -> Result<(), String> {
+        self.require_operation(TxKind::Burn)?;
+        self.throttle(from)?;
+        match self.pending_burns.get(&from) {
+            None => return Err("No pending burn".to_string()),
+            Some(pending) if *pending != amount => {
+                return Err(format!("Pending burn is for {}", pending));
+            }
+            Some(_) => {}
+        }
+        self.execute_burn(from, amount)?;
+        self.pending_burns.remove(&from);
+        Ok(())
+    }
+
+    pub fn cancel_burn(&mut self, from: Principal) -> Result<(), String> {
+        self.pending_burns.remove(&from).map(|_| ()).ok_or_else(|| "No pending burn".to_string())
+    }
+
+    pub fn pending_burn(&self, account: Principal) -> Option<u128> {
+        self.pending_burns.get(&account).copied()
+    }
+
+    pub fn large_burn_threshold(&self) -> Option<u128> {
+        self.large_burn_threshold
+    }
+
+    pub fn set_large_burn_threshold(&mut self, threshold: Option<u128>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the large burn threshold".to_string());
+        }
+        self.large_burn_threshold = threshold;
+        Ok(())
+    }
+
+    fn execute_burn(&mut self, from: Principal, amount: u128) -> Result<(), String> {
+        self.check_send_lock(from)?;
+        if self.spendable_balance(from) < amount {
+            return Err("Insufficient balance".to_string());
+        }
+        self.clear_expired_lock(from);
+        *self.balances.entry(from).or_insert(0) -= amount;
+        self.total_supply -= amount;
+        self.burned_total += amount;
+        let record = TransactionRecord {
+            from,
+            to: self.burn_account,
+            amount,
+            post_balance_from: self.balance_of(from),
+            post_balance_to: 0,
+            cycles_burnt: 0,
+            reason: "Tokens were burned and removed from supply.".to_string(),
+            kind: TxKind::Burn,
+            timestamp: env::time(),
+            memo: None,
+            fee: 0,
+            caller: Some(env::caller()),
+            authorized_by: Some(from),
+        };
+        self.push_record(record);
+        Ok(())
+    }
+
+    pub fn burned_total(&self) -> u128 {
+        self.burned_total
+    }
+
+    pub fn deflation_schedule(&self) -> Option<DeflationSchedule> {
+        self.deflation
+    }
+
+    pub fn last_deflation_burn(&self) -> u64 {
+        self.last_deflation_burn
+    }
+
+    // The first interval is counted from now, not from the last burn
+    pub fn set_deflation_schedule(&mut self, schedule: Option<DeflationSchedule>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the deflation schedule".to_string());
+        }
+        if schedule.is_some_and(|s| s.rate_bps > 10_000 || s.interval_ns == 0) {
+            return Err("Deflation needs a rate of at most 10000 bps and a nonzero interval".to_string());
+        }
+        self.deflation = schedule;
+        self.last_deflation_burn = env::time();
+        Ok(())
+    }
+
+    // Burns at most once per pass, so missed intervals aren't made up.
+    // Skipped while burning is disabled. Returns the amount burned.
+    fn run_deflation(&mut self) -> u128 {
+        let Some(schedule) = self.deflation else {
+            return 0;
+        };
+        let now = env::time();
+        if self.require_operation(TxKind::Burn).is_err() || now.saturating_sub(self.last_deflation_burn) < schedule.interval_ns {
+            return 0;
+        }
+        self.last_deflation_burn = now;
+        let treasury = self.fee_collector;
+        let amount = self.bps_portion(self.spendable_balance(treasury), schedule.rate_bps);
+        if amount == 0 || self.execute_burn(treasury, amount).is_err() {
+            return 0;
+        }
+        amount
+    }
+
+    pub fn burn_account(&self) -> Principal {
+        self.burn_account
+    }
+
+    pub fn set_burn_account(&mut self, account: Principal) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the burn account".to_string());
+        }
+        self.burn_account = account;
+        Ok(())
+    }
+
+    // Passes `result` through, logging it first if it is an error
+    pub fn log_failure<T>(&mut self, operation: &str, caller: Principal, result: Result<T, String>) -> Result<T, String> {
+        if let Err(error) = &result {
+            self.failed_operations.push_back(FailedOp {
+                operation: operation.to_string(),
+                caller,
+                error: error.clone(),
+                timestamp: env::time(),
+            });
+            self.trim_event_log();
+            *self.error_counts.entry(error.clone()).or_insert(0) += 1;
+        }
+        result
+    }
+
+    fn trim_event_log(&mut self) {
+        while self.failed_operations.len() as u64 > self.event_log_capacity {
+            self.failed_operations.pop_front();
+            self.dropped_events += 1;
+        }
+    }
+
+    pub fn event_log_capacity(&self) -> u64 {
+        self.event_log_capacity
+    }
+
+    // Shrinking below the current length drops the oldest entries at once
+    pub fn set_event_log_capacity(&mut self, capacity: u64) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can size the event log".to_string());
+        }
+        if capacity == 0 || capacity > MAX_EVENT_LOG_CAPACITY {
+            return Err(format!("Event log capacity must be between 1 and {}", MAX_EVENT_LOG_CAPACITY));
+        }
+        self.event_log_capacity = capacity;
+        self.trim_event_log();
+        Ok(())
+    }
+
+    // Nonzero means get_failed_operations no longer shows every failure
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped_events
+    }
+
+    pub fn get_failed_operations(&self, start: u64, length: u64) -> Vec<FailedOp> {
+        self.failed_operations
+            .iter()
+            .skip(start as usize)
+            .take(length as usize)
+            .cloned()
+            .collect()
+    }
+
+    pub fn error_counts(&self) -> Vec<(String, u64)> {
+        let mut counts: Vec<(String, u64)> = self.error_counts.iter().map(|(e, c)| (e.clone(), *c)).collect();
+        counts.sort();
+        counts
+    }
+
+    // Split (multiply) or reverse-split (divide) every amount-denominated value
+    // by `factor`. Decimals are deliberately left alone: moving them with the
+    // factor would cancel the split in every displayed amount, which is what
+    // migrate_decimals is for.
+    pub fn redenominate(&mut self, factor: u64, multiply: bool) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can redenominate".to_string());
+        }
+        if factor == 0 {
+            return Err("Factor must be nonzero".to_string());
+        }
+        self.rescale(factor as u128, multiply)
+    }
+
+    // Corrective one-off for a token launched with the wrong decimals: amounts
+    // are rescaled by 10^(new - old) so every displayed value stays the same
+    pub fn migrate_decimals(&mut self, new_decimals: u8) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can migrate decimals".to_string());
+        }
+        if new_decimals == self.decimals {
+            return Err("Decimals unchanged".to_string());
+        }
+        check_decimals(new_decimals)?;
+        let factor = 10u128
+            .checked_pow(new_decimals.abs_diff(self.decimals) as u32)
+            .ok_or_else(|| "Redenomination would overflow".to_string())?;
+        self.rescale(factor, new_decimals > self.decimals)?;
+        self.decimals = new_decimals;
+        Ok(())
+    }
+
+    // All values are checked before any is written, so an overflow or uneven
+    // division leaves the ledger untouched. The cached price is per token of
+    // the old scale, so it's dropped and fetched again.
+    fn rescale(&mut self, factor: u128, multiply: bool) -> Result<(), String> {
+        let scale = |v: u128| -> Result<u128, String> {
+            if multiply {
+                v.checked_mul(factor).ok_or_else(|| "Redenomination would overflow".to_string())
+            } else if !v.is_multiple_of(factor) {
+                Err("Redenomination would lose precision".to_string())
+            } else {
+                Ok(v / factor)
+            }
+        };
+        let scale_map = |m: &HashMap<Principal, u128>| -> Result<HashMap<Principal, u128>, String> {
+            m.iter().map(|(k, v)| Ok((*k, scale(*v)?))).collect()
+        };
+        let balances = scale_map(&self.balances)?;
+        let held = scale_map(&self.held)?;
+        let time_locks = self
+            .time_locks
+            .iter()
+            .map(|(account, locks)| {
+                let scaled = locks
+                    .iter()
+                    .map(|l| Ok(TimeLock { amount: scale(l.amount)?, unlock_at: l.unlock_at }))
+                    .collect::<Result<Vec<_>, String>>()?;
+                Ok((*account, scaled))
+            })
+            .collect::<Result<HashMap<_, _>, String>>()?;
+        let allowances = self
+            .allowances
+            .iter()
+            .map(|(owner, spenders)| Ok((*owner, scale_map(spenders)?)))
+            .collect::<Result<HashMap<_, _>, String>>()?;
+        let total_supply = scale(self.total_supply)?;
+        let burned_total = scale(self.burned_total)?;
+        let existential_deposit = scale(self.existential_deposit)?;
+        let large_burn_threshold = self.large_burn_threshold.map(scale).transpose()?;
+        let pending_burns = scale_map(&self.pending_burns)?;
+        let max_allowance = self.max_allowance.map(scale).transpose()?;
+        let max_tx_amount = self.max_tx_amount.map(scale).transpose()?;
+        let max_wallet = self.max_wallet.map(scale).transpose()?;
+        let staking_emission = scale(self.staking_emission)?;
+        let fee = scale(self.fee)?;
+        let fee_free_below = scale(self.fee_free_below)?;
+        let approve_fee = scale(self.approve_fee)?;
+        let min_approval_amount = scale(self.min_approval_amount)?;
+        let transfer_from_fee = self.transfer_from_fee.map(scale).transpose()?;
+        let stakes = self
+            .stakes
+            .iter()
+            .map(|(account, st)| {
+                let scaled = StakeInfo { amount: scale(st.amount)?, accrued: scale(st.accrued)?, ..*st };
+                Ok((*account, scaled))
+            })
+            .collect::<Result<HashMap<_, _>, String>>()?;
+        let proposal_amounts = self
+            .mint_proposals
+            .iter()
+            .map(|(id, p)| Ok((*id, scale(p.amount)?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let token_reserve = scale(self.token_reserve)?;
+        let queued_transfers = self
+            .queued_transfers
+            .iter()
+            .map(|q| Ok(QueuedTransfer { amount: scale(q.amount)?, ..q.clone() }))
+            .collect::<Result<VecDeque<_>, String>>()?;
+
+        self.balances = balances;
+        self.held = held;
+        self.time_locks = time_locks;
+        self.stakes = stakes;
+        self.allowances = allowances;
+        self.total_supply = total_supply;
+        self.burned_total = burned_total;
+        self.existential_deposit = existential_deposit;
+        self.large_burn_threshold = large_burn_threshold;
+        self.pending_burns = pending_burns;
+        self.max_allowance = max_allowance;
+        self.max_tx_amount = max_tx_amount;
+        self.max_wallet = max_wallet;
+        self.staking_emission = staking_emission;
+        self.fee = fee;
+        self.fee_free_below = fee_free_below;
+        self.approve_fee = approve_fee;
+        self.min_approval_amount = min_approval_amount;
+        self.transfer_from_fee = transfer_from_fee;
+        self.queued_transfers = queued_transfers;
+        self.token_reserve = token_reserve;
+        self.cached_price = None;
+        for (id, amount) in proposal_amounts {
+            if let Some(p) = self.mint_proposals.get_mut(&id) {
+                p.amount = amount;
+            }
+        }
+        Ok(())
+    }
+
+    // Every record is charged the execution cost metered since the previous one
+    fn push_record(&mut self, mut record: TransactionRecord) {
+        record.cycles_burnt = metered_cycles();
+        self.burnt_cycles = self.burnt_cycles.saturating_add(record.cycles_burnt);
+        self.tip_hash = Some(chain_hash(self.tip_hash.as_ref(), &record));
+        self.index_record(self.transaction_history.len() as u64, &record);
+        self.transaction_history.push(record);
+    }
+
+    fn index_record(&mut self, index: u64, record: &TransactionRecord) {
+        self.account_index.entry(record.from).or_default().push(index);
+        if record.to != record.from {
+            self.account_index.entry(record.to).or_default().push(index);
+        }
+    }
+
+    pub fn account_tx_count(&self, account: Principal) -> u64 {
+        self.account_index.get(&account).map_or(0, |indices| indices.len() as u64)
+    }
+
+    // Received minus sent (fees included) over records stamped in
+    // [start_ns, end_ns). Mints count only for the recipient and burns only
+    // for the burner; an approve moves nothing but its fee. Transfer fees
+    // credited to the fee collector aren't in its index, so they don't count
+    // as the collector's inflow.
+    pub fn net_flow(&self, account: Principal, start_ns: u64, end_ns: u64) -> i128 {
+        let Some(indices) = self.account_index.get(&account) else {
+            return 0;
+        };
+        let timestamp = |i: &u64| self.transaction_history.get(*i as usize).map_or(0, |r| r.timestamp);
+        let first = indices.partition_point(|i| timestamp(i) < start_ns);
+        let as_flow = |v: u128| i128::try_from(v).unwrap_or(i128::MAX);
+        indices[first..]
+            .iter()
+            .filter_map(|i| self.transaction_history.get(*i as usize))
+            .take_while(|r| r.timestamp < end_ns)
+            .fold(0i128, |net, r| {
+                let received = match r.kind {
+                    TxKind::Approve | TxKind::Burn => 0,
+                    _ if r.to == account => as_flow(r.amount),
+                    _ => 0,
+                };
+                let sent = match r.kind {
+                    TxKind::Mint => 0,
+                    TxKind::Approve if r.from == account => as_flow(r.fee),
+                    _ if r.from == account => as_flow(r.amount.saturating_add(r.fee)),
+                    _ => 0,
+                };
+                net.saturating_add(received).saturating_sub(sent)
+            })
+    }
+
+    // Whether any record has `a` and `b` as its two parties, either way round.
+    // A record is indexed under both its parties, so a shared index is such a
+    // record; pairing an account with itself asks about self-transfers.
+    pub fn have_transacted(&self, a: Principal, b: Principal) -> bool {
+        let (Some(left), Some(right)) = (self.account_index.get(&a), self.account_index.get(&b)) else {
+            return false;
+        };
+        if a == b {
+            return left
+                .iter()
+                .filter_map(|i| self.transaction_history.get(*i as usize))
+                .any(|r| r.from == a && r.to == a);
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < left.len() && j < right.len() {
+            match left[i].cmp(&right[j]) {
+                Ordering::Less => i += 1,
+                Ordering::Greater => j += 1,
+                Ordering::Equal => return true,
+            }
+        }
+        false
+    }
+
+    // Latest block index and its chained hash, or None on an empty ledger
+    pub fn tip(&self) -> Option<(u64, Vec<u8>)> {
+        let hash = self.tip_hash?;
+        let index = self.transaction_history.len().checked_sub(1)? as u64;
+        Some((index, hash.to_vec()))
+    }
+
+    // Content hash of one record, independent of its position in the chain
+    pub fn transaction_hash(&self, index: u64) -> Option<Vec<u8>> {
+        let record = self.transaction_history.get(usize::try_from(index).ok()?)?;
+        Some(record_hash(record).to_vec())
+    }
+
+    // (first index, later index) for each record whose content hash repeats
+    // an earlier one. Identical entries of one batch_transfer share a
+    // timestamp and so show up here too; they are not double-logged.
+    pub fn find_duplicate_transactions(&self) -> Vec<(u64, u64)> {
+        let mut first_seen: HashMap<[u8; 32], u64> = HashMap::new();
+        let mut duplicates = Vec::new();
+        for (index, record) in self.transaction_history.iter().enumerate() {
+            let index = index as u64;
+            match first_seen.entry(record_hash(record)) {
+                Entry::Occupied(first) => duplicates.push((*first.get(), index)),
+                Entry::Vacant(slot) => {
+                    slot.insert(index);
+                }
+            }
+        }
+        duplicates
+    }
+
+    pub fn feature_flags(&self) -> FeatureFlags {
+        self.feature_flags
+    }
+
+    pub fn set_feature_flags(&mut self, flags: FeatureFlags) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set feature flags".to_string());
+        }
+        self.feature_flags = flags;
+        Ok(())
+    }
+
+    // A pause is separate from the feature flags: the flags say what the
+    // ledger offers, a pause stops one operation for a while. In queue mode
+    // paused transfers wait for the unpause instead of failing.
+    pub fn pause_operation(&mut self, kind: TxKind) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can pause operations".to_string());
+        }
+        if matches!(kind, TxKind::Reap | TxKind::Reclaim | TxKind::Rollback) {
+            return Err("Operation cannot be paused".to_string());
+        }
+        self.paused_operations.insert(kind);
+        Ok(())
+    }
+
+    pub fn unpause_operation(&mut self, kind: TxKind) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can pause operations".to_string());
+        }
+        if self.paused_operations.remove(&kind) && kind == TxKind::Transfer {
+            self.run_queued_transfers();
+        }
+        Ok(())
+    }
+
+    pub fn paused_operations(&self) -> Vec<TxKind> {
+        let mut kinds: Vec<TxKind> = self.paused_operations.iter().copied().collect();
+        kinds.sort_by_key(|kind| kind.tag());
+        kinds
+    }
+
+    // An operation runs while its feature flag is on and it isn't paused
+    fn require_operation(&self, kind: TxKind) -> Result<(), String> {
+        require_enabled(self.feature_flags.enabled(kind))?;
+        if self.paused_operations.contains(&kind) {
+            return Err("Operation paused".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn maintenance_interval(&self) -> Option<u64> {
+        self.maintenance_interval_ns
+    }
+
+    pub fn set_maintenance_interval(&mut self, interval_ns: Option<u64>) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can configure maintenance".to_string());
+        }
+        if interval_ns == Some(0) {
+            return Err("Maintenance interval must be nonzero".to_string());
+        }
+        self.maintenance_interval_ns = interval_ns;
+        Ok(())
+    }
+
+    pub fn config(&self) -> LedgerConfig {
+        LedgerConfig {
+            fee: self.fee,
+            fee_free_below: self.fee_free_below,
+            fee_payer: self.fee_payer,
+            approve_fee: self.approve_fee,
+            min_approval_amount: self.min_approval_amount,
+            transfer_from_fee: self.transfer_from_fee,
+            fiat_fee: self.fiat_fee,
+            price_oracle: self.price_oracle,
+            fee_collector: self.fee_collector,
+            rounding_mode: self.rounding_mode,
+            mint_fee_bps: self.mint_fee_bps,
+            mint_threshold: self.mint_threshold,
+            max_minters: self.max_minters,
+            minter_activation_delay_ns: self.minter_activation_delay_ns,
+            require_custody_ref: self.require_custody_ref,
+            user_registry: self.user_registry,
+            mint_hook: self.mint_hook,
+            staking_emission: self.staking_emission,
+            max_tx_amount: self.max_tx_amount,
+            max_wallet: self.max_wallet,
+            max_allowance: self.max_allowance,
+            existential_deposit: self.existential_deposit,
+            require_memo: self.require_memo,
+            memo_uniqueness_window_ns: self.memo_uniqueness_window_ns,
+            require_recipient_whitelist: self.require_recipient_whitelist,
+            trading_enabled: self.trading_enabled,
+            accept_direct_deposits: self.accept_direct_deposits,
+            hide_frozen_balances: self.hide_frozen_balances,
+            large_burn_threshold: self.large_burn_threshold,
+            burn_account: self.burn_account,
+            inactivity_period_ns: self.inactivity_period_ns,
+            rate_limit: self.rate_limit,
+            feature_flags: self.feature_flags,
+            pause_mode: self.pause_mode,
+            deflation: self.deflation,
+            icp_ledger: self.icp_ledger,
+            icp_fee: self.icp_fee,
+            pool_fee_bps: self.pool_fee_bps,
+            event_log_capacity: self.event_log_capacity,
+        }
+    }
+
+    // All or nothing: everything the individual setters would reject is
+    // checked before any field changes. Settings whose setters reset caches or
+    // settle stakes go through those setters, and only when they change, so
+    // writing back an unchanged config has no side effects.
+    pub fn set_config(&mut self, config: LedgerConfig) -> Result<(), String> {
+        if env::caller() != self.owner {
+            return Err("Only the owner can set the configuration".to_string());
+        }
+        if config.inactivity_period_ns.is_some_and(|p| p < MIN_INACTIVITY_PERIOD_NS) {
+            return Err("Inactivity period must be at least one year".to_string());
+        }
+        if config.max_minters.is_some_and(|c| (c as usize) < self.minters.len()) {
+            return Err("Minter limit is below the current minter count".to_string());
+        }
+        if config.mint_fee_bps > 10_000 {
+            return Err("Mint fee cannot exceed 10000 bps".to_string());
+        }
+        if config.mint_threshold == 0 || config.mint_threshold as usize > self.minters.len() {
+            return Err("Threshold must be between 1 and the number of minters".to_string());
+        }
+        if config.deflation.is_some_and(|s| s.rate_bps > 10_000 || s.interval_ns == 0) {
+            return Err("Deflation needs a rate of at most 10000 bps and a nonzero interval".to_string());
+        }
+        if config.pool_fee_bps > 10_000 {
+            return Err("Pool fee cannot exceed 10000 bps".to_string());
+        }
+        if config.event_log_capacity == 0 || config.event_log_capacity > MAX_EVENT_LOG_CAPACITY {
+            return Err(format!("Event log capacity must be between 1 and {}", MAX_EVENT_LOG_CAPACITY));
+        }
+        if config.icp_ledger != self.icp_ledger && !self.pool_is_empty() {
+            return Err("Pool still holds liquidity".to_string());
+        }
+        if config.price_oracle != self.price_oracle {
+            self.set_price_oracle(config.price_oracle)?;
+        }
+        if config.user_registry != self.user_registry {
+            self.set_user_registry(config.user_registry)?;
+        }
+        if config.rate_limit != self.rate_limit {
+            self.set_rate_limit(config.rate_limit)?;
+        }
+        if config.deflation != self.deflation {
+            self.set_deflation_schedule(config.deflation)?;
+        }
+        if config.staking_emission != self.staking_emission {
+            self.set_staking_emission(config.staking_emission)?;
+        }
+        self.fee = config.fee;
+        self.fee_free_below = config.fee_free_below;
+        self.fee_payer = config.fee_payer;
+        self.approve_fee = config.approve_fee;
+        self.min_approval_amount = config.min_approval_amount;
+        self.transfer_from_fee = config.transfer_from_fee;
+        self.fiat_fee = config.fiat_fee;
+        self.fee_collector = config.fee_collector;
+        self.rounding_mode = config.rounding_mode;
+        self.mint_fee_bps = config.mint_fee_bps;
+        self.mint_threshold = config.mint_threshold;
+        self.max_minters = config.max_minters;
+        self.minter_activation_delay_ns = config.minter_activation_delay_ns;
+        self.require_custody_ref = config.require_custody_ref;
+        self.mint_hook = config.mint_hook;
+        self.max_tx_amount = config.max_tx_amount;
+        self.max_wallet = config.max_wallet;
+        self.max_allowance = config.max_allowance;
+        self.existential_deposit = config.existential_deposit;
+        self.require_memo = config.require_memo;
+        self.memo_uniqueness_window_ns = config.memo_uniqueness_window_ns;
+        self.require_recipient_whitelist = config.require_recipient_whitelist;
+        self.trading_enabled = config.trading_enabled;
+        self.accept_direct_deposits = config.accept_direct_deposits;
+        self.hide_frozen_balances = config.hide_frozen_balances;
+        self.large_burn_threshold = config.large_burn_threshold;
+        self.burn_account = config.burn_account;
+        self.inactivity_period_ns = config.inactivity_period_ns;
+        self.pause_mode = config.pause_mode;
+        self.icp_ledger = config.icp_ledger;
+        self.icp_fee = config.icp_fee;
+        self.pool_fee_bps = config.pool_fee_bps;
+        self.event_log_capacity = config.event_log_capacity;
+        self.trim_event_log();
+        self.feature_flags = config.feature_flags;
+        Ok(())
+    }
+
+    // Never ICRC-3: records are hash-chained, but no ICRC-3 block endpoints are served
+    pub fn ledger_type(&self) -> LedgerType {
+        if self.feature_flags.allowances_enabled() {
+            LedgerType::Icrc2
+        } else {
+            LedgerType::Icrc1
+        }
+    }
+
+    pub fn icrc1_supported_standards(&self) -> Vec<StandardRecord> {
+        let mut standards = vec![StandardRecord {
+            name: "ICRC-1".to_string(),
+            url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-1".to_string(),
+        }];
+        if self.feature_flags.allowances_enabled() {
+            standards.push(StandardRecord {
+                name: "ICRC-2".to_string(),
+                url: "https://github.com/dfinity/ICRC-1/tree/main/standards/ICRC-2".to_string(),
+            });
+        }
+        standards
+    }
+
+    // The ICRC-1 list plus ICRC-10, which defines this entrypoint
+    pub fn icrc10_supported_standards(&self) -> Vec<StandardRecord> {
+        let mut standards = self.icrc1_supported_standards();
+        standards.push(StandardRecord {
+            name: "ICRC-10".to_string(),
+            url: "https://github.com/dfinity/ICRC/blob/main/ICRCs/ICRC-10/ICRC-10.md".to_string(),
+        });
+        standards
+    }
+
+    // O(n log n) in the number of holders; refused above
+    // DISTRIBUTION_STATS_MAX_HOLDERS to stay inside the query instruction limit
+    pub fn distribution_stats(&self) -> Result<DistributionStats, String> {
+        let holders = self.balances.values().filter(|b| **b > 0).count();
+        if holders > DISTRIBUTION_STATS_MAX_HOLDERS {
+            return Err("Too many holders for distribution stats".to_string());
+        }
+        let n = holders as u128;
+        let total = self.balances.values().fold(0u128, |acc, b| acc.saturating_add(*b));
+        if total == 0 {
+            return Ok(DistributionStats::default());
+        }
+        // Shift holdings down until the total fits in 64 bits so the
+        // weighted sums below can't overflow; shares barely move
+        let shift = (u128::BITS - total.leading_zeros()).saturating_sub(64);
+        let mut holdings: Vec<u128> = self.balances.values().filter(|b| **b > 0).map(|b| *b >> shift).collect();
+        let total: u128 = holdings.iter().sum();
+        if total == 0 {
+            return Ok(DistributionStats::default());
+        }
+        holdings.sort_unstable();
+        let share_of_top = |pct: u128| -> u16 {
+            let count = (n * pct).div_ceil(100).max(1) as usize;
+            let top: u128 = holdings.iter().rev().take(count).sum();
+            (top * 10_000 / total) as u16
+        };
+        // G = (2 * sum(i * x_i)) / (n * total) - (n + 1) / n over ascending x_1..x_n
+        let weighted: u128 = holdings.iter().zip(1u128..).map(|(x, i)| i * x).sum();
+        let gini = (2 * weighted).saturating_sub((n + 1) * total) * 10_000 / (n * total);
+        Ok(DistributionStats {
+            holder_count: n as u64,
+            top1_bps: share_of_top(1),
+            top10_bps: share_of_top(10),
+            gini_bps: gini as u16,
+        })
+    }
+
+    // Holders with at least `threshold`, largest first and ties by principal so
+    // pages stay stable. `exclude_system` drops the owner, fee collector and
+    // exempt accounts, e.g. for a vote-eligibility list. Pages cap at 1000.
+    pub fn accounts_above(
+        &self,
+        threshold: u128,
+        exclude_system: bool,
+        start: u64,
+        length: u64,
+    ) -> Vec<(Principal, u128)> {
+        let mut accounts: Vec<(Principal, u128)> = self
+            .balances
+            .iter()
+            .filter(|(_, b)| **b > 0 && **b >= threshold)
+            .filter(|(p, _)| !exclude_system || !(self.is_exempt(**p) || **p == self.fee_collector))
+            .map(|(p, b)| (*p, *b))
+            .collect();
+        accounts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.as_slice().cmp(b.0.as_slice())));
+        accounts
+            .into_iter()
+            .skip(start as usize)
+            .take(length.min(1_000) as usize)
+            .collect()
+    }
+
+    // Nonzero balances sorted by principal bytes, so the tree is deterministic
+    fn balance_leaves(&self) -> Vec<(Principal, [u8; 32])> {
+        let mut holders: Vec<(&Principal, &u128)> = self.balances.iter().filter(|(_, b)| **b > 0).collect();
+        holders.sort_by(|a, b| a.0.as_slice().cmp(b.0.as_slice()));
+        holders
+            .into_iter()
+            .map(|(p, b)| (*p, merkle::leaf_hash(p, *b)))
+            .collect()
+    }
+
+    pub fn balances_merkle_root(&self) -> Vec<u8> {
+        let leaves: Vec<[u8; 32]> = self.balance_leaves().into_iter().map(|(_, h)| h).collect();
+        merkle::root(&leaves).to_vec()
+    }
+
+    // Inclusion proof for the account's current balance; None if it holds nothing
+    pub fn balance_proof(&self, account: Principal) -> Option<Vec<Vec<u8>>> {
+        let leaves = self.balance_leaves();
+        let index = leaves.iter().position(|(p, _)| *p == account)?;
+        let hashes: Vec<[u8; 32]> = leaves.into_iter().map(|(_, h)| h).collect();
+        Some(merkle::proof(&hashes, index))
+    }
+
+    pub fn get_transaction_history(&self) -> Vec<TransactionRecord> {
+        self.transaction_history.clone()
+    }
+
+    // Newest first, capped at 100 records
+    pub fn recent_transactions(&self, n: u64) -> Vec<TransactionRecord> {
+        let n = n.min(100) as usize;
+        self.transaction_history.iter().rev().take(n).cloned().collect()
+    }
+
+    // One slot per requested index, in request order; only the first 100
+    // indices are looked up
+    pub fn get_transactions_by_index(&self, indices: &[u64]) -> Vec<Option<TransactionRecord>> {
+        indices
+            .iter()
+            .take(100)
+            .map(|i| usize::try_from(*i).ok().and_then(|i| self.transaction_history.get(i)).cloned())
+            .collect()
+    }
+
+    // Records touching `account` with start_ns <= timestamp < end_ns, oldest
+    // first. Walks only the account's index, not the whole history.
+    pub fn account_statement(&self, account: Principal, start_ns: u64, end_ns: u64) -> Vec<TransactionRecord> {
+        let mut records: Vec<TransactionRecord> = self
+            .account_index
+            .get(&account)
+            .map(|indices| {
+                indices
+                    .iter()
+                    .filter_map(|i| self.transaction_history.get(*i as usize))
+                    .filter(|r| r.timestamp >= start_ns && r.timestamp < end_ns)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        records.sort_by_key(|r| r.timestamp);
+        records
+    }
+
+    // Sum of transferred amounts with start_ns <= timestamp < end_ns. Rollbacks
+    // have their own kind, so a reversed transfer_and_notify counts once. Linear
+    // in history length; daily buckets can be cached here if history grows large.
+    pub fn volume_in_window(&self, start_ns: u64, end_ns: u64) -> u128 {
+        self.transaction_history
+            .iter()
+            .filter(|r| matches!(r.kind, TxKind::Transfer | TxKind::TransferFrom))
+            .filter(|r| r.timestamp >= start_ns && r.timestamp < end_ns)
+            .fold(0u128, |acc, r| acc.saturating_add(r.amount))
+    }
+}
+
+impl Default for TokenICRC2 {
+    fn default() -> Self {
+        Self::new(Principal::anonymous(), 0, 0, String::new(), String::new())
+    }
+}
+
+// Bump on any change to the persisted layout, and when migrate_state needs a
+// value fixup
+const STATE_VERSION: u32 = 6;
+
+// Every query and update method the canister exposes, including `greet` from
+// lib.rs. Keep in step with task1_backend.did when adding endpoints.
+const METHOD_NAMES: &[&str] = &[
+    "accept_direct_deposits",
+    "account_locked_until",
+    "account_statement",
+    "account_summary",
+    "account_tx_count",
+    "accounts_above",
+    "add_liquidity",
+    "add_minter",
+    "allowance",
+    "approval_count",
+    "approve",
+    "approve_fee",
+    "approve_mint",
+    "balance_of",
+    "balance_proof",
+    "balance_value_in_icp",
+    "balances_merkle_root",
+    "batch_transfer",
+    "burn",
+    "burn_account",
+    "burn_cycles",
+    "burned_total",
+    "burnt_cycles",
+    "cancel_burn",
+    "certified_metadata",
+    "claim_icp",
+    "claim_rewards",
+    "confirm_burn",
+    "current_apy_bps",
+    "cycles_by_operation",
+    "dashboard",
+    "decimals",
+    "deflation_schedule",
+    "distribute_pro_rata",
+    "distribution_stats",
+    "dropped_events",
+    "effective_fee",
+    "error_counts",
+    "event_log_capacity",
+    "existential_deposit",
+    "expiring_allowances",
+    "feature_flags",
+    "fee",
+    "fee_collector",
+    "fee_free_below",
+    "fee_payer",
+    "fiat_fee",
+    "find_duplicate_transactions",
+    "force_revoke_allowance",
+    "force_revoke_spender",
+    "force_transfer",
+    "get_config",
+    "get_failed_operations",
+    "get_mint_proposal",
+    "get_transaction_history",
+    "get_transactions_by_index",
+    "greet",
+    "have_transacted",
+    "hide_frozen_balances",
+    "icp_owed",
+    "icrc10_supported_standards",
+    "icrc1_balance_of",
+    "icrc1_supported_standards",
+    "inactivity_period",
+    "init_token",
+    "is_exempt",
+    "is_trading_whitelisted",
+    "is_whitelisted_recipient",
+    "large_burn_threshold",
+    "last_activity",
+    "last_deflation_burn",
+    "ledger_type",
+    "lock_account_until",
+    "lp_shares",
+    "maintenance_interval",
+    "max_allowance",
+    "max_minters",
+    "max_tx_amount",
+    "max_wallet",
+    "memo_uniqueness_window",
+    "method_names",
+    "migrate_decimals",
+    "min_approval_amount",
+    "mint",
+    "mint_fee_bps",
+    "mint_hook",
+    "mint_locked",
+    "mint_threshold",
+    "minter_activation_delay",
+    "name",
+    "net_flow",
+    "parse_amount",
+    "pause_mode",
+    "pause_operation",
+    "paused_operations",
+    "pending_burn",
+    "pending_operations",
+    "pending_rewards",
+    "pool_info",
+    "price_oracle",
+    "propose_burn",
+    "propose_mint",
+    "propose_reclaim",
+    "queued_transfers",
+    "rate_limit",
+    "reap_expired",
+    "reassign_allowance",
+    "recent_transactions",
+    "reclaim_inactive",
+    "redenominate",
+    "remove_liquidity",
+    "renounce_allowance",
+    "require_custody_ref",
+    "require_memo",
+    "require_recipient_whitelist",
+    "rounding_mode",
+    "set_accept_direct_deposits",
+    "set_approve_fee",
+    "set_auto_compound",
+    "set_burn_account",
+    "set_config",
+    "set_deflation_schedule",
+    "set_event_log_capacity",
+    "set_exempt",
+    "set_existential_deposit",
+    "set_feature_flags",
+    "set_fee",
+    "set_fee_collector",
+    "set_fee_free_below",
+    "set_fee_payer",
+    "set_fiat_fee",
+    "set_hide_frozen_balances",
+    "set_icp_fee",
+    "set_icp_ledger",
+    "set_inactivity_period",
+    "set_large_burn_threshold",
+    "set_max_allowance",
+    "set_max_minters",
+    "set_max_tx_amount",
+    "set_max_wallet",
+    "set_memo_uniqueness_window",
+    "set_min_approval_amount",
+    "set_mint_fee_bps",
+    "set_mint_hook",
+    "set_mint_threshold",
+    "set_minter_activation_delay",
+    "set_pause_mode",
+    "set_pool_fee_bps",
+    "set_price_oracle",
+    "set_rate_limit",
+    "set_require_custody_ref",
+    "set_require_memo",
+    "set_require_recipient_whitelist",
+    "set_rounding_mode",
+    "set_spend_authorizer",
+    "set_staking_emission",
+    "set_trading_enabled",
+    "set_trading_whitelisted",
+    "set_transfer_from_fee",
+    "set_user_registry",
+    "set_whitelisted_recipient",
+    "simulate_transfer",
+    "spend_authorizer",
+    "spendable_balance",
+    "stake",
+    "stake_info",
+    "staking_emission",
+    "start_maintenance",
+    "stop_maintenance",
+    "supply_drift",
+    "swap_icp_for_token",
+    "swap_token_for_icp",
+    "symbol",
+    "time_locks",
+    "tip",
+    "total_allowance_for_spender",
+    "total_staked",
+    "total_supply",
+    "total_supply_display",
+    "trading_enabled",
+    "transaction_hash",
+    "transfer",
+    "transfer_all",
+    "transfer_and_notify",
+    "transfer_from",
+    "transfer_from_fee",
+    "unpause_operation",
+    "unstake",
+    "user_registry",
+    "verify_supply_invariant",
+    "volume_in_window",
+];
+
+thread_local! {
+    static TOKEN_ICRC2: std::cell::RefCell<Option<TokenICRC2>> = const { std::cell::RefCell::new(None) };
+    static MAINTENANCE_TIMER: std::cell::RefCell<Option<ic_cdk_timers::TimerId>> = const { std::cell::RefCell::new(None) };
+}
+
+// Replaces any running maintenance timer. Timers live outside stable memory,
+// so post_upgrade calls this again with the persisted interval.
+fn arm_maintenance(interval_ns: Option<u64>) {
+    MAINTENANCE_TIMER.with(|timer| {
+        if let Some(id) = timer.borrow_mut().take() {
+            ic_cdk_timers::clear_timer(id);
+        }
+        if let Some(interval_ns) = interval_ns {
+            let interval = std::time::Duration::from_nanos(interval_ns);
+            *timer.borrow_mut() = Some(ic_cdk_timers::set_timer_interval(interval, run_maintenance));
+        }
+    });
+}
+
+fn run_maintenance() {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow_mut().as_mut() {
+            t.reap_expired();
+        }
+    });
+}
+
+// One-shot: a ledger that is already set up is never overwritten. Returns
+// the token as created, so the caller sees the canonical configuration.
+#[ic_cdk_macros::update]
+fn init_token(
+    symbol: String,
+    name: String,
+    total_supply: u128,
+    decimals: u8,
+    initial_allocations: Option<Vec<(Principal, u128)>>,
+) -> Result<Token, String> {
+    let info = install_token(ic_cdk::caller(), symbol, name, total_supply, decimals, initial_allocations)?;
+    certify_metadata();
+    Ok(info)
+}
+
+// init_token without the system calls: `owner` is the caller and the
+// metadata is left for the endpoint to certify
+fn install_token(
+    owner: Principal,
+    symbol: String,
+    name: String,
+    total_supply: u128,
+    decimals: u8,
+    initial_allocations: Option<Vec<(Principal, u128)>>,
+) -> Result<Token, String> {
+    if TOKEN_ICRC2.with(|token| token.borrow().is_some()) {
+        return Err("Token already initialized".to_string());
+    }
+    if symbol.trim().is_empty() || name.trim().is_empty() {
+        return Err("Symbol and name must be nonempty".to_string());
+    }
+    check_decimals(decimals)?;
+    let mut state = TokenICRC2::new(owner, total_supply, decimals, name, symbol);
+    if let Some(allocations) = initial_allocations {
+        state.allocate_genesis(allocations)?;
+    }
+    let info = state.token_info();
+    TOKEN_ICRC2.with(|token| {
+        *token.borrow_mut() = Some(state);
+    });
+    Ok(info)
+}
+
+// Call after anything that changes symbol, name or decimals. post_upgrade
+// calls it too, so the certificate always matches the restored state.
+fn certify_metadata() {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            ic_cdk::api::set_certified_data(&t.certified_data());
+        }
+    });
+}
+
+#[ic_cdk_macros::pre_upgrade]
+fn pre_upgrade() {
+    TOKEN_ICRC2.with(|token| {
+        ic_cdk::storage::stable_save((STATE_VERSION, &*token.borrow()))
+            .expect("Failed to save token state");
+    });
+}
+
+#[ic_cdk_macros::post_upgrade]
+fn post_upgrade() {
+    let state = restore_state(&ic_cdk::api::stable::stable_bytes());
+    let interval_ns = state.as_ref().and_then(|t| t.maintenance_interval());
+    TOKEN_ICRC2.with(|token| {
+        *token.borrow_mut() = state;
+    });
+    arm_maintenance(interval_ns);
+    certify_metadata();
+}
+
+// Decodes what pre_upgrade saved. The version is read first, since each
+// older layout decodes through its own struct in `legacy`. A snapshot that
+// held state but decodes to nothing panics, trapping the upgrade, rather than
+// leave an empty ledger that anyone could initialize.
+pub fn restore_state(snapshot: &[u8]) -> Option<TokenICRC2> {
+    let (version, saved) = decode_snapshot::<candid::Reserved>(snapshot);
+    let state = if version < 5 {
+        decode_snapshot::<legacy::TokenICRC2V4>(snapshot).1.map(TokenICRC2::from)
+    } else if version == 5 {
+        decode_snapshot::<legacy::TokenICRC2V5>(snapshot).1.map(TokenICRC2::from)
+    } else {
+        decode_snapshot::<TokenICRC2>(snapshot).1
+    };
+    if saved.is_some() && state.is_none() {
+        panic!("Stable snapshot v{} does not decode into its layout", version);
+    }
+    migrate_state(version, state)
+}
+
+// Stable memory runs past the snapshot, so trailing bytes aren't an error
+fn decode_snapshot<T>(snapshot: &[u8]) -> (u32, Option<T>)
+where
+    T: CandidType + for<'de> Deserialize<'de>,
+{
+    let mut de = candid::de::IDLDeserialize::new(snapshot).expect("Failed to restore token state");
+    let version = de.get_value::<u32>().expect("Failed to restore token state");
+    let state = de.get_value::<Option<T>>().expect("Failed to restore token state");
+    (version, state)
+}
+
+// Brings a snapshot written by an older build up to STATE_VERSION. Fields
+// the snapshot lacked already hold their defaults from the legacy
+// conversion; any per-version value fixups go here, keyed on `version`.
+pub fn migrate_state(version: u32, state: Option<TokenICRC2>) -> Option<TokenICRC2> {
+    if version > STATE_VERSION {
+        ic_cdk::trap("Stable snapshot is newer than this build");
+    }
+    let mut state = state;
+    if version < 2 {
+        // v1 records carry no kind; mints were the only records without a sender balance
+        if let Some(t) = state.as_mut() {
+            for record in t.transaction_history.iter_mut() {
+                if record.post_balance_from == 0 && record.reason.starts_with("Minting") {
+                    record.kind = TxKind::Mint;
+                }
+            }
+        }
+    }
+    if version < 5 {
+        // v2 had no hash chain, and v4 hashed amounts as 8 bytes; rebuild it
+        // from the existing history
+        if let Some(t) = state.as_mut() {
+            t.tip_hash = t
+                .transaction_history
+                .iter()
+                .fold(None, |parent, record| Some(chain_hash(parent.as_ref(), record)));
+        }
+    }
+    if version < 4 {
+        // v3 had no per-account index
+        if let Some(t) = state.as_mut() {
+            let history = std::mem::take(&mut t.transaction_history);
+            t.account_index.clear();
+            for (i, record) in history.iter().enumerate() {
+                t.index_record(i as u64, record);
+            }
+            t.transaction_history = history;
+        }
+    }
+    state
+}
+
+#[ic_cdk_macros::update]
+fn add_minter(minter: Principal) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.add_minter(minter)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+async fn mint(to: Principal, amount: u128, custody_ref: Option<String>) -> Result<(), String> {
+    verify_registration(to).await?;
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.mint(to, amount, custody_ref);
+            t.log_failure("mint", ic_cdk::caller(), result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn distribute_pro_rata(total_amount: u128, custody_ref: Option<String>) -> Result<u64, String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.distribute_pro_rata(total_amount, custody_ref);
+            t.log_failure("distribute_pro_rata", ic_cdk::caller(), result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn icrc1_balance_of(account: Account) -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.icrc1_balance_of(&account)
+        } else {
+            0
+        }
+    })
+}
+
+// Legacy principal-only entry point, kept for existing clients
+#[ic_cdk_macros::query]
+fn balance_of(user: Principal) -> u128 {
+    icrc1_balance_of(Account { owner: user, subaccount: None })
+}
+
+#[ic_cdk_macros::query]
+fn spendable_balance(account: Principal) -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.spendable_balance(account)
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn total_supply() -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.total_supply()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn symbol() -> String {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.symbol()
+        } else {
+            "".to_string()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn name() -> String {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.name()
+        } else {
+            "".to_string()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn decimals() -> u8 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.decimals()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn allowance(owner: Principal, spender: Principal) -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.allowance(owner, spender)
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn approval_count(owner: Principal) -> u64 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.approval_count(owner)
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn approve(spender: Principal, amount: u128, expires_at: Option<u64>, fee: Option<u128>) -> Result<u128, String> {
+    let owner = ic_cdk::caller();
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.approve(owner, spender, amount, expires_at, fee);
+            t.log_failure("approve", owner, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn max_allowance() -> Option<u128> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.max_allowance()
+        } else {
+            None
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_max_allowance(ceiling: Option<u128>) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_max_allowance(ceiling)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+// Refreshes the cached oracle price when it has gone stale. An unreachable
+// oracle leaves the cache stale, so fees fall back to the flat fee.
+async fn refresh_price() {
+    let due = TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.price_refresh_due()));
+    let Some(oracle) = due else {
+        return;
+    };
+    let result: ic_cdk::api::call::CallResult<(u64,)> = ic_cdk::call(oracle, "get_price", ()).await;
+    if let Ok((price,)) = result {
+        TOKEN_ICRC2.with(|token| {
+            if let Some(ref mut t) = token.borrow_mut().as_mut() {
+                t.record_price(price);
+            }
+        });
+    }
+}
+
+// Asks the account's registered authorizer, if any, to approve a spend.
+// Fails closed: a trap, reject, or `false` from the authorizer blocks it.
+async fn authorize_spend(from: Principal, to: Principal, amount: u128) -> Result<(), String> {
+    let authorizer = TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.spend_authorizer(from)));
+    let Some(authorizer) = authorizer else {
+        return Ok(());
+    };
+    let result: ic_cdk::api::call::CallResult<(bool,)> =
+        ic_cdk::call(authorizer, "authorize_spend", (from, to, amount)).await;
+    spend_decision(result)
+}
+
+// Only an explicit `true` lets the spend through
+fn spend_decision(reply: ic_cdk::api::call::CallResult<(bool,)>) -> Result<(), String> {
+    match reply {
+        Ok((true,)) => Ok(()),
+        _ => Err("Spend not authorized".to_string()),
+    }
+}
+
+// Sends `amount` ICP from the ledger canister's account; `fee` is the ICP
+// fee the pool set aside on top of it
+async fn send_icp(ledger: Principal, to: Principal, amount: u128, fee: u128) -> Result<(), String> {
+    let arg = IcpTransferArg {
+        from_subaccount: None,
+        to: Account { owner: to, subaccount: None },
+        amount,
+        fee: Some(fee),
+        memo: None,
+        created_at_time: None,
+    };
+    let result: ic_cdk::api::call::CallResult<(Result<candid::Nat, candid::Reserved>,)> =
+        ic_cdk::call(ledger, "icrc1_transfer", (arg,)).await;
+    match result {
+        Ok((Ok(_),)) => Ok(()),
+        Ok((Err(_),)) => Err("ICP ledger rejected the transfer".to_string()),
+        Err((code, msg)) => Err(format!("ICP ledger call failed ({:?}): {}", code, msg)),
+    }
+}
+
+// Pulls ICP the caller approved for the ledger canister on the ICP ledger.
+// The ICP ledger fee on the pull is the caller's, on top of `amount`.
+async fn pull_icp(ledger: Principal, from: Principal, amount: u128) -> Result<(), String> {
+    let arg = IcpTransferFromArgs {
+        spender_subaccount: None,
+        from: Account { owner: from, subaccount: None },
+        to: Account { owner: ic_cdk::id(), subaccount: None },
+        amount,
+        fee: None,
+        memo: None,
+        created_at_time: None,
+    };
+    let result: ic_cdk::api::call::CallResult<(Result<candid::Nat, candid::Reserved>,)> =
+        ic_cdk::call(ledger, "icrc2_transfer_from", (arg,)).await;
+    match result {
+        Ok((Ok(_),)) => Ok(()),
+        Ok((Err(_),)) => Err("ICP ledger rejected the transfer; check the ICP approval".to_string()),
+        Err((code, msg)) => Err(format!("ICP ledger call failed ({:?}): {}", code, msg)),
+    }
+}
+
+// Pays out swap-pool ICP, keeping it claimable if the ICP ledger refuses
+async fn pay_icp(ledger: Principal, to: Principal, amount: u128) -> Result<(), String> {
+    let fee = TOKEN_ICRC2.with(|token| token.borrow().as_ref().map_or(0, |t| t.icp_fee()));
+    let result = send_icp(ledger, to, amount, fee).await;
+    if result.is_err() {
+        TOKEN_ICRC2.with(|token| {
+            if let Some(ref mut t) = token.borrow_mut().as_mut() {
+                t.owe_icp(to, amount + fee);
+            }
+        });
+    }
+    result
+}
+
+// Asks the user registry, if any, whether `to` may receive mints. Fails
+// closed: a trap or reject from the registry blocks the mint like a `false`.
+async fn verify_registration(to: Principal) -> Result<(), String> {
+    let due = TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.registry_check_due(to)));
+    let Some(registry) = due else {
+        return Ok(());
+    };
+    let result: ic_cdk::api::call::CallResult<(bool,)> = ic_cdk::call(registry, "is_registered", (to,)).await;
+    match result {
+        Ok((true,)) => {
+            TOKEN_ICRC2.with(|token| {
+                if let Some(ref mut t) = token.borrow_mut().as_mut() {
+                    t.record_registration(to);
+                }
+            });
+            Ok(())
+        }
+        Ok((false,)) => Err("Recipient is not registered".to_string()),
+        Err(_) => Err("User registry unavailable".to_string()),
+    }
+}
+
+#[ic_cdk_macros::update]
+fn set_spend_authorizer(authorizer: Option<Principal>) -> Result<(), String> {
+    let account = ic_cdk::caller();
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_spend_authorizer(account, authorizer);
+            Ok(())
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn spend_authorizer(account: Principal) -> Option<Principal> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.spend_authorizer(account)))
+}
+
+#[ic_cdk_macros::update]
+async fn transfer(to: Principal, amount: u128, memo: Option<Vec<u8>>) -> Result<(), String> {
+    let from = ic_cdk::caller();
+    refresh_price().await;
+    authorize_spend(from, to, amount).await?;
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.transfer(from, to, amount, memo);
+            t.log_failure("transfer", from, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+async fn transfer_from(from: Principal, to: Principal, amount: u128, memo: Option<Vec<u8>>) -> Result<(), String> {
+    let spender = ic_cdk::caller();
+    refresh_price().await;
+    authorize_spend(from, to, amount).await?;
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.transfer_from(spender, from, to, amount, memo);
+            t.log_failure("transfer_from", spender, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn require_memo() -> bool {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.require_memo()
+        } else {
+            false
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_require_memo(required: bool) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_require_memo(required)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn rounding_mode() -> RoundingMode {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.rounding_mode()
+        } else {
+            RoundingMode::Down
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_rounding_mode(mode: RoundingMode) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_rounding_mode(mode)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn burn_cycles(cycles: u64) {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.burn_cycles(cycles);
+        }
+    });
+}
+
+#[ic_cdk_macros::query]
+fn burnt_cycles() -> u64 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.burnt_cycles()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn cycles_by_operation() -> Vec<(TxKind, u64)> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.cycles_by_operation()
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn get_transaction_history() -> Vec<TransactionRecord> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.get_transaction_history()
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn volume_in_window(start_ns: u64, end_ns: u64) -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.volume_in_window(start_ns, end_ns)
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn lock_account_until(account: Principal, until_ns: u64) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.lock_account_until(account, until_ns)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn account_locked_until(account: Principal) -> Option<u64> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.account_locked_until(account)
+        } else {
+            None
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn fee_collector() -> Option<Principal> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().map(|t| t.fee_collector()))
+}
+
+#[ic_cdk_macros::update]
+fn set_fee_collector(collector: Principal) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_fee_collector(collector)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn existential_deposit() -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.existential_deposit()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_existential_deposit(deposit: u128) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_existential_deposit(deposit)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn burn(amount: u128) -> Result<(), String> {
+    let from = ic_cdk::caller();
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.burn(from, amount);
+            t.log_failure("burn", from, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn burned_total() -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.burned_total()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn burn_account() -> Principal {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.burn_account()
+        } else {
+            Principal::management_canister()
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_burn_account(account: Principal) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_burn_account(account)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn accept_direct_deposits() -> bool {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.accept_direct_deposits()
+        } else {
+            false
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_accept_direct_deposits(accept: bool) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_accept_direct_deposits(accept)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn recent_transactions(n: u64) -> Vec<TransactionRecord> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.recent_transactions(n)
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn mint_threshold() -> u32 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.mint_threshold()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_mint_threshold(threshold: u32) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_mint_threshold(threshold)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+async fn propose_mint(to: Principal, amount: u128, custody_ref: Option<String>) -> Result<u64, String> {
+    verify_registration(to).await?;
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.propose_mint(to, amount, custody_ref)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+// Re-checks the recipient, since the cached confirmation from propose_mint
+// may have lapsed by the time the threshold is reached
+#[ic_cdk_macros::update]
+async fn approve_mint(proposal_id: u64) -> Result<bool, String> {
+    let proposal = TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.get_mint_proposal(proposal_id)));
+    if let Some(proposal) = proposal {
+        verify_registration(proposal.to).await?;
+    }
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.approve_mint(proposal_id)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn get_mint_proposal(proposal_id: u64) -> Option<MintProposal> {
+    TOKEN_ICRC2.with(|token| {
+        token
+            .borrow()
+            .as_ref()
+            .and_then(|t| t.get_mint_proposal(proposal_id))
+    })
+}
+
+// Transfers to a canister and calls its `on_token_received(from, amount, data)`.
+// The credited amount stays held until the callback returns, and the transfer
+// is reversed if the receiver rejects or traps.
+#[ic_cdk_macros::update]
+async fn transfer_and_notify(to_canister: Principal, amount: u128, data: Vec<u8>) -> Result<(), String> {
+    let from = ic_cdk::caller();
+    refresh_price().await;
+    authorize_spend(from, to_canister, amount).await?;
+    // The receiver is told, and holds, what it was credited; that is less
+    // than `amount` when the recipient pays the fee
+    let received = TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.transfer_and_hold(from, to_canister, amount)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })?;
+    let result: ic_cdk::api::call::CallResult<()> =
+        ic_cdk::call(to_canister, "on_token_received", (from, received, data)).await;
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            match result {
+                Ok(()) => t.release_hold(to_canister, received),
+                Err((code, msg)) => {
+                    t.rollback_transfer(from, to_canister, received)?;
+                    Err(format!("Receiver rejected transfer ({:?}): {}", code, msg))
+                }
+            }
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn get_failed_operations(start: u64, length: u64) -> Vec<FailedOp> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.get_failed_operations(start, length)
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn error_counts() -> Vec<(String, u64)> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.error_counts()
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn event_log_capacity() -> u64 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.event_log_capacity()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_event_log_capacity(capacity: u64) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_event_log_capacity(capacity)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn dropped_events() -> u64 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.dropped_events()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn redenominate(factor: u64, multiply: bool) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.redenominate(factor, multiply)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn total_allowance_for_spender(spender: Principal) -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.total_allowance_for_spender(spender)
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn is_exempt(account: Principal) -> bool {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.is_exempt(account)
+        } else {
+            false
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_exempt(account: Principal, exempt: bool) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_exempt(account, exempt)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn require_recipient_whitelist() -> bool {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.require_recipient_whitelist()
+        } else {
+            false
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_require_recipient_whitelist(required: bool) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_require_recipient_whitelist(required)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn is_whitelisted_recipient(account: Principal) -> bool {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.is_whitelisted_recipient(account)
+        } else {
+            false
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_whitelisted_recipient(account: Principal, whitelisted: bool) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_whitelisted_recipient(account, whitelisted)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn trading_enabled() -> bool {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.trading_enabled()
+        } else {
+            false
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_trading_enabled(enabled: bool) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_trading_enabled(enabled)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn is_trading_whitelisted(account: Principal) -> bool {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.is_trading_whitelisted(account)
+        } else {
+            false
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_trading_whitelisted(account: Principal, whitelisted: bool) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_trading_whitelisted(account, whitelisted)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn max_tx_amount() -> Option<u128> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.max_tx_amount()))
+}
+
+#[ic_cdk_macros::update]
+fn set_max_tx_amount(cap: Option<u128>) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_max_tx_amount(cap)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn max_wallet() -> Option<u128> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.max_wallet()))
+}
+
+#[ic_cdk_macros::update]
+fn set_max_wallet(cap: Option<u128>) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_max_wallet(cap)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn tip() -> Option<(u64, Vec<u8>)> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.tip()))
+}
+
+#[ic_cdk_macros::query]
+fn transaction_hash(index: u64) -> Option<Vec<u8>> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.transaction_hash(index)))
+}
+
+#[ic_cdk_macros::query]
+fn feature_flags() -> FeatureFlags {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.feature_flags()
+        } else {
+            FeatureFlags::default()
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_feature_flags(flags: FeatureFlags) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_feature_flags(flags)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn pause_operation(kind: TxKind) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.pause_operation(kind)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn unpause_operation(kind: TxKind) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.unpause_operation(kind)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn paused_operations() -> Vec<TxKind> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.paused_operations()
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn icrc1_supported_standards() -> Vec<StandardRecord> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.icrc1_supported_standards()
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn icrc10_supported_standards() -> Vec<StandardRecord> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.icrc10_supported_standards()
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn fee() -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.fee()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_fee(fee: u128) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_fee(fee)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+// Entries are independent transfers from the caller: one failing leaves the
+// others standing, and each is authorized and fee-charged on its own
+#[ic_cdk_macros::update]
+async fn batch_transfer(transfers: Vec<(Principal, u128)>, memo: Option<Vec<u8>>) -> Result<BatchResult, String> {
+    if transfers.len() > BATCH_TRANSFER_MAX {
+        return Err(format!("Batch exceeds {} transfers", BATCH_TRANSFER_MAX));
+    }
+    let from = ic_cdk::caller();
+    refresh_price().await;
+    let mut batch = BatchResult::default();
+    for (index, (to, amount)) in transfers.into_iter().enumerate() {
+        let outcome = match authorize_spend(from, to, amount).await {
+            Ok(()) => TOKEN_ICRC2.with(|token| {
+                if let Some(ref mut t) = token.borrow_mut().as_mut() {
+                    let result = t.batch_transfer_entry(from, to, amount, memo.clone());
+                    t.log_failure("batch_transfer", from, result)
+                } else {
+                    Err("Token not initialized".to_string())
+                }
+            }),
+            Err(e) => Err(e),
+        };
+        batch.push(index as u64, amount, outcome);
+    }
+    Ok(batch)
+}
+
+// Returns the amount sent
+#[ic_cdk_macros::update]
+async fn transfer_all(to: Principal, memo: Option<Vec<u8>>) -> Result<u128, String> {
+    let from = ic_cdk::caller();
+    refresh_price().await;
+    // Fix the amount up front so an authorizer approves exactly what is sent
+    let (amount, fee) = TOKEN_ICRC2.with(|token| match token.borrow().as_ref() {
+        Some(t) => t.transfer_all_quote(from),
+        None => Err("Token not initialized".to_string()),
+    })?;
+    authorize_spend(from, to, amount).await?;
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.transfer_with_fee(from, to, amount, fee, memo).map(|_| amount);
+            t.log_failure("transfer_all", from, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn expiring_allowances(within_ns: u64) -> Vec<(Principal, Principal, u64)> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.expiring_allowances(within_ns)
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+async fn mint_locked(to: Principal, amount: u128, unlock_at: u64, custody_ref: Option<String>) -> Result<(), String> {
+    verify_registration(to).await?;
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.mint_locked(to, amount, unlock_at, custody_ref);
+            t.log_failure("mint_locked", ic_cdk::caller(), result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn time_locks(account: Principal) -> Vec<TimeLock> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.time_locks(account)
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn total_supply_display() -> String {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.total_supply_display()
+        } else {
+            "".to_string()
+        }
+    })
+}
+
+// Converts a human-entered amount to base units at the token's decimals
+#[ic_cdk_macros::query]
+fn parse_amount(input: String) -> Result<u128, String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.parse_amount(&input)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn account_tx_count(account: Principal) -> u64 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.account_tx_count(account)
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn ledger_type() -> LedgerType {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.ledger_type()
+        } else {
+            LedgerType::Icrc1
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn effective_fee() -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.effective_fee()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn price_oracle() -> Option<Principal> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.price_oracle()))
+}
+
+#[ic_cdk_macros::update]
+fn set_price_oracle(oracle: Option<Principal>) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_price_oracle(oracle)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn fiat_fee() -> Option<u64> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.fiat_fee()))
+}
+
+#[ic_cdk_macros::update]
+fn set_fiat_fee(fiat_fee: Option<u64>) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_fiat_fee(fiat_fee)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn rate_limit() -> Option<RateLimit> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.rate_limit()))
+}
+
+#[ic_cdk_macros::update]
+fn set_rate_limit(limit: Option<RateLimit>) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_rate_limit(limit)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn balances_merkle_root() -> Vec<u8> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.balances_merkle_root()
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn balance_proof(account: Principal) -> Option<Vec<Vec<u8>>> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.balance_proof(account)))
+}
+
+#[ic_cdk_macros::query]
+fn supply_drift() -> i128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.supply_drift()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn verify_supply_invariant() -> bool {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.verify_supply_invariant()
+        } else {
+            true
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn approve_fee() -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.approve_fee()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_approve_fee(fee: u128) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_approve_fee(fee)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn min_approval_amount() -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.min_approval_amount()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_min_approval_amount(amount: u128) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_min_approval_amount(amount)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+// Spender list is capped to keep the query bounded
+#[ic_cdk_macros::query]
+fn dashboard(account: Principal, spenders: Vec<Principal>) -> Option<DashboardData> {
+    let spenders: Vec<Principal> = spenders.into_iter().take(100).collect();
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().map(|t| t.dashboard(account, spenders)))
+}
+
+#[ic_cdk_macros::update]
+fn migrate_decimals(new_decimals: u8) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.migrate_decimals(new_decimals)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })?;
+    certify_metadata();
+    Ok(())
+}
+
+#[ic_cdk_macros::update]
+fn reap_expired() -> ReapSummary {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.reap_expired()
+        } else {
+            ReapSummary::default()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn maintenance_interval() -> Option<u64> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.maintenance_interval()))
+}
+
+#[ic_cdk_macros::update]
+fn start_maintenance(interval_ns: u64) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_maintenance_interval(Some(interval_ns))
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })?;
+    arm_maintenance(Some(interval_ns));
+    Ok(())
+}
+
+#[ic_cdk_macros::update]
+fn stop_maintenance() -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_maintenance_interval(None)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })?;
+    arm_maintenance(None);
+    Ok(())
+}
+
+#[ic_cdk_macros::query]
+fn account_summary(account: Principal) -> Option<AccountSummary> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().map(|t| t.account_summary(account)))
+}
+
+#[ic_cdk_macros::query]
+fn transfer_from_fee() -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.transfer_from_fee()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_transfer_from_fee(fee: Option<u128>) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_transfer_from_fee(fee)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn method_names() -> Vec<String> {
+    METHOD_NAMES.iter().map(|name| name.to_string()).collect()
+}
+
+#[ic_cdk_macros::query]
+fn mint_fee_bps() -> u16 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.mint_fee_bps()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_mint_fee_bps(bps: u16) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_mint_fee_bps(bps)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn force_revoke_allowance(owner: Principal, spender: Principal) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.force_revoke_allowance(owner, spender)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn force_revoke_spender(spender: Principal) -> Result<u64, String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.force_revoke_spender(spender)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn renounce_allowance(owner: Principal) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.renounce_allowance(owner);
+            t.log_failure("renounce_allowance", ic_cdk::caller(), result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn hide_frozen_balances() -> bool {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.hide_frozen_balances()
+        } else {
+            false
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_hide_frozen_balances(hide: bool) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_hide_frozen_balances(hide)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn simulate_transfer(from: Principal, to: Principal, amount: u128) -> Option<SimulationResult> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().map(|t| t.simulate_transfer(from, to, amount)))
+}
+
+#[ic_cdk_macros::query]
+fn max_minters() -> Option<u32> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.max_minters()))
+}
+
+#[ic_cdk_macros::update]
+fn set_max_minters(cap: Option<u32>) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_max_minters(cap)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn minter_activation_delay() -> u64 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.minter_activation_delay()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_minter_activation_delay(delay_ns: u64) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_minter_activation_delay(delay_ns)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn account_statement(account: Principal, start_ns: u64, end_ns: u64) -> Vec<TransactionRecord> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.account_statement(account, start_ns, end_ns)
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn propose_burn(amount: u128) -> Result<(), String> {
+    let from = ic_cdk::caller();
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.propose_burn(from, amount);
+            t.log_failure("propose_burn", from, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn confirm_burn(amount: u128) -> Result<(), String> {
+    let from = ic_cdk::caller();
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.confirm_burn(from, amount);
+            t.log_failure("confirm_burn", from, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn cancel_burn() -> Result<(), String> {
+    let from = ic_cdk::caller();
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.cancel_burn(from)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn pending_burn(account: Principal) -> Option<u128> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.pending_burn(account)))
+}
+
+#[ic_cdk_macros::query]
+fn large_burn_threshold() -> Option<u128> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.large_burn_threshold()))
+}
+
+#[ic_cdk_macros::update]
+fn set_large_burn_threshold(threshold: Option<u128>) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_large_burn_threshold(threshold)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn distribution_stats() -> Result<DistributionStats, String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.distribution_stats()
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn last_activity(account: Principal) -> Option<u64> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.last_activity(account)))
+}
+
+#[ic_cdk_macros::query]
+fn inactivity_period() -> Option<u64> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.inactivity_period()))
+}
+
+#[ic_cdk_macros::update]
+fn set_inactivity_period(period_ns: Option<u64>) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_inactivity_period(period_ns)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn propose_reclaim(account: Principal, to: Principal) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.propose_reclaim(account, to)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn reclaim_inactive(account: Principal, to: Principal) -> Result<u128, String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.reclaim_inactive(account, to)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn user_registry() -> Option<Principal> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.user_registry()))
+}
+
+#[ic_cdk_macros::update]
+fn set_user_registry(registry: Option<Principal>) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_user_registry(registry)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn accounts_above(threshold: u128, exclude_system: bool, start: u64, length: u64) -> Vec<(Principal, u128)> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.accounts_above(threshold, exclude_system, start, length)
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn fee_free_below() -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.fee_free_below()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_fee_free_below(threshold: u128) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_fee_free_below(threshold)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn pending_operations() -> Option<PendingOps> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().map(|t| t.pending_operations()))
+}
+
+#[ic_cdk_macros::query]
+fn require_custody_ref() -> bool {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.require_custody_ref()
+        } else {
+            false
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_require_custody_ref(required: bool) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_require_custody_ref(required)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn certified_metadata() -> Option<CertifiedMetadata> {
+    let certificate = ic_cdk::api::data_certificate();
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().map(|t| t.certified_metadata(certificate)))
+}
+
+#[ic_cdk_macros::update]
+fn set_auto_compound(enabled: bool) -> Result<(), String> {
+    let account = ic_cdk::caller();
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_auto_compound(account, enabled)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn stake(amount: u128) -> Result<(), String> {
+    let account = ic_cdk::caller();
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.stake(account, amount);
+            t.log_failure("stake", account, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn unstake(amount: u128) -> Result<(), String> {
+    let account = ic_cdk::caller();
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.unstake(account, amount);
+            t.log_failure("unstake", account, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn claim_rewards() -> Result<u128, String> {
+    let account = ic_cdk::caller();
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.claim_rewards(account);
+            t.log_failure("claim_rewards", account, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
 
-    pub fn burn_cycles(&mut self, cycles: u64) {
-        self.burnt_cycles += cycles;
-    }
+#[ic_cdk_macros::query]
+fn stake_info(account: Principal) -> Option<StakeInfo> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.stake_info(account)))
+}
 
-    pub fn burnt_cycles(&self) -> u64 {
-        self.burnt_cycles
-    }
-    pub fn add_minter(&mut self, minter: Principal) -> Result<(), String> {
-        let owner = ic_cdk::caller();
-        if owner != self.get_owner() {
-            return Err("Only the owner can add minters".to_string());
+#[ic_cdk_macros::query]
+fn pending_rewards(account: Principal) -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.pending_rewards(account)
+        } else {
+            0
         }
-        self.minters.insert(minter);
-        Ok(())
-    }
+    })
+}
 
-    pub fn mint(&mut self, to: Principal, amount: u64) -> Result<(), String> {
-        let caller = ic_cdk::caller();
-        if !self.minters.contains(&caller) {
-            return Err("Caller is not authorized to mint".to_string());
+#[ic_cdk_macros::query]
+fn staking_emission() -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.staking_emission()
+        } else {
+            0
         }
-        *self.balances.entry(to).or_insert(0) += amount;
-        self.total_supply += amount;
-        let record = TransactionRecord {
-            from: caller,
-            to,
-            amount,
-            post_balance_from: 0,
-            post_balance_to: self.balances.get(&to).copied().unwrap_or(0),
-            cycles_burnt: 0,
-            reason: "Minting operation has no cycle burn cost.".to_string(),
-        };
-        self.transaction_history.push(record);
-        Ok(())
-    }
-    pub fn get_transaction_history(&self) -> Vec<TransactionRecord> {
-        self.transaction_history.clone()
-    }
+    })
 }
 
-thread_local! {
-    static TOKEN_ICRC2: std::cell::RefCell<Option<TokenICRC2>> = std::cell::RefCell::new(None);
+#[ic_cdk_macros::update]
+fn set_staking_emission(per_year: u128) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_staking_emission(per_year)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
 }
 
 #[ic_cdk_macros::update]
-fn init_token(symbol: String, name: String, total_supply: u64, decimals: u8) {
+fn reassign_allowance(old_spender: Principal, new_spender: Principal) -> Result<u128, String> {
     let owner = ic_cdk::caller();
     TOKEN_ICRC2.with(|token| {
-        *token.borrow_mut() = Some(TokenICRC2::new(owner, total_supply, decimals, name, symbol));
-    });
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.reassign_allowance(owner, old_spender, new_spender);
+            t.log_failure("reassign_allowance", owner, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn memo_uniqueness_window() -> Option<u64> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.memo_uniqueness_window()))
 }
+
 #[ic_cdk_macros::update]
-fn add_minter(minter: Principal) -> Result<(), String> {
+fn set_memo_uniqueness_window(window_ns: Option<u64>) -> Result<(), String> {
     TOKEN_ICRC2.with(|token| {
         if let Some(ref mut t) = token.borrow_mut().as_mut() {
-            t.add_minter(minter)
+            t.set_memo_uniqueness_window(window_ns)
         } else {
             Err("Token not initialized".to_string())
         }
     })
 }
 
+#[ic_cdk_macros::query]
+fn get_config() -> Option<LedgerConfig> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().map(|t| t.config()))
+}
+
 #[ic_cdk_macros::update]
-fn mint(to: Principal, amount: u64) -> Result<(), String> {
+fn set_config(config: LedgerConfig) -> Result<(), String> {
     TOKEN_ICRC2.with(|token| {
         if let Some(ref mut t) = token.borrow_mut().as_mut() {
-            t.mint(to, amount)
+            t.set_config(config)
         } else {
             Err("Token not initialized".to_string())
         }
     })
 }
+
 #[ic_cdk_macros::query]
-fn balance_of(user: Principal) -> u64 {
+fn pause_mode() -> PauseMode {
     TOKEN_ICRC2.with(|token| {
         if let Some(t) = token.borrow().as_ref() {
-            t.balance_of(user)
+            t.pause_mode()
         } else {
-            0
+            PauseMode::Reject
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_pause_mode(mode: PauseMode) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_pause_mode(mode)
+        } else {
+            Err("Token not initialized".to_string())
         }
     })
 }
 
 #[ic_cdk_macros::query]
-fn total_supply() -> u64 {
+fn queued_transfers() -> Vec<QueuedTransfer> {
     TOKEN_ICRC2.with(|token| {
         if let Some(t) = token.borrow().as_ref() {
-            t.total_supply()
+            t.queued_transfers()
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn total_staked() -> u128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.total_staked()
         } else {
             0
         }
@@ -219,32 +6012,32 @@ fn total_supply() -> u64 {
 }
 
 #[ic_cdk_macros::query]
-fn symbol() -> String {
+fn current_apy_bps() -> u64 {
     TOKEN_ICRC2.with(|token| {
         if let Some(t) = token.borrow().as_ref() {
-            t.symbol()
+            t.current_apy_bps()
         } else {
-            "".to_string()
+            0
         }
     })
 }
 
 #[ic_cdk_macros::query]
-fn name() -> String {
+fn pool_info() -> PoolInfo {
     TOKEN_ICRC2.with(|token| {
         if let Some(t) = token.borrow().as_ref() {
-            t.name()
+            t.pool_info()
         } else {
-            "".to_string()
+            PoolInfo::default()
         }
     })
 }
 
 #[ic_cdk_macros::query]
-fn decimals() -> u8 {
+fn lp_shares(account: Principal) -> u128 {
     TOKEN_ICRC2.with(|token| {
         if let Some(t) = token.borrow().as_ref() {
-            t.decimals()
+            t.lp_shares(account)
         } else {
             0
         }
@@ -252,10 +6045,10 @@ fn decimals() -> u8 {
 }
 
 #[ic_cdk_macros::query]
-fn allowance(owner: Principal, spender: Principal) -> u64 {
+fn icp_owed(account: Principal) -> u128 {
     TOKEN_ICRC2.with(|token| {
         if let Some(t) = token.borrow().as_ref() {
-            t.allowance(owner, spender)
+            t.icp_owed(account)
         } else {
             0
         }
@@ -263,11 +6056,10 @@ fn allowance(owner: Principal, spender: Principal) -> u64 {
 }
 
 #[ic_cdk_macros::update]
-fn approve(spender: Principal, amount: u64) -> Result<(), String> {
-    let owner = ic_cdk::caller();
+fn set_icp_ledger(ledger: Option<Principal>) -> Result<(), String> {
     TOKEN_ICRC2.with(|token| {
         if let Some(ref mut t) = token.borrow_mut().as_mut() {
-            t.approve(owner, spender, amount)
+            t.set_icp_ledger(ledger)
         } else {
             Err("Token not initialized".to_string())
         }
@@ -275,11 +6067,10 @@ fn approve(spender: Principal, amount: u64) -> Result<(), String> {
 }
 
 #[ic_cdk_macros::update]
-fn transfer(to: Principal, amount: u64) -> Result<(), String> {
-    let from = ic_cdk::caller();
+fn set_icp_fee(fee: u128) -> Result<(), String> {
     TOKEN_ICRC2.with(|token| {
         if let Some(ref mut t) = token.borrow_mut().as_mut() {
-            t.transfer(from, to, amount)
+            t.set_icp_fee(fee)
         } else {
             Err("Token not initialized".to_string())
         }
@@ -287,31 +6078,248 @@ fn transfer(to: Principal, amount: u64) -> Result<(), String> {
 }
 
 #[ic_cdk_macros::update]
-fn burn_cycles(cycles: u64) {
+fn set_pool_fee_bps(bps: u16) -> Result<(), String> {
     TOKEN_ICRC2.with(|token| {
         if let Some(ref mut t) = token.borrow_mut().as_mut() {
-            t.burn_cycles(cycles);
+            t.set_pool_fee_bps(bps)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+// The caller approves the ledger canister for icp_amount plus the ICP fee on
+// the ICP ledger first. Returns the pool shares minted.
+#[ic_cdk_macros::update]
+async fn add_liquidity(token_amount: u128, icp_amount: u128) -> Result<u128, String> {
+    let provider = ic_cdk::caller();
+    authorize_spend(provider, ic_cdk::id(), token_amount).await?;
+    let ledger = TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.begin_add_liquidity(provider, token_amount, icp_amount);
+            t.log_failure("add_liquidity", provider, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })?;
+    let pulled = pull_icp(ledger, provider, icp_amount).await;
+    let result = TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = match pulled {
+                Ok(()) => t.finish_add_liquidity(provider, token_amount, icp_amount),
+                Err(e) => t.abort_add_liquidity(provider, token_amount).and(Err(e)),
+            };
+            t.log_failure("add_liquidity", provider, result)
+        } else {
+            Err("Token not initialized".to_string())
         }
     });
+    result
+}
+
+// Returns the (token, ICP) amounts paid out; the ICP is net of the ICP fee
+#[ic_cdk_macros::update]
+async fn remove_liquidity(shares: u128) -> Result<(u128, u128), String> {
+    let provider = ic_cdk::caller();
+    let (ledger, token_out, icp_out) = TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.remove_liquidity(provider, shares);
+            t.log_failure("remove_liquidity", provider, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })?;
+    pay_icp(ledger, provider, icp_out).await?;
+    Ok((token_out, icp_out))
+}
+
+// Returns the ICP received, net of the ICP fee
+#[ic_cdk_macros::update]
+async fn swap_token_for_icp(token_in: u128, min_icp_out: u128) -> Result<u128, String> {
+    let trader = ic_cdk::caller();
+    authorize_spend(trader, ic_cdk::id(), token_in).await?;
+    let (ledger, icp_out) = TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.swap_token_for_icp(trader, token_in, min_icp_out);
+            t.log_failure("swap_token_for_icp", trader, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })?;
+    pay_icp(ledger, trader, icp_out).await?;
+    Ok(icp_out)
+}
+
+// The caller approves the ledger canister for icp_in plus the ICP fee on the
+// ICP ledger first. Returns the tokens received.
+#[ic_cdk_macros::update]
+async fn swap_icp_for_token(icp_in: u128, min_token_out: u128) -> Result<u128, String> {
+    let trader = ic_cdk::caller();
+    let (ledger, quote) = TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.quote_icp_for_token(icp_in)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })?;
+    if quote == 0 || quote < min_token_out {
+        return Err("Slippage limit exceeded".to_string());
+    }
+    pull_icp(ledger, trader, icp_in).await?;
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.swap_icp_for_token(trader, icp_in, min_token_out);
+            t.log_failure("swap_icp_for_token", trader, result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+// Retries ICP payouts that failed earlier; returns the ICP sent, net of the fee
+#[ic_cdk_macros::update]
+async fn claim_icp() -> Result<u128, String> {
+    let account = ic_cdk::caller();
+    let (ledger, owed, fee) = TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let (ledger, owed) = t.take_icp_owed(account)?;
+            Ok((ledger, owed, t.icp_fee()))
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })?;
+    pay_icp(ledger, account, owed - fee).await?;
+    Ok(owed - fee)
 }
 
 #[ic_cdk_macros::query]
-fn burnt_cycles() -> u64 {
+fn find_duplicate_transactions() -> Vec<(u64, u64)> {
     TOKEN_ICRC2.with(|token| {
         if let Some(t) = token.borrow().as_ref() {
-            t.burnt_cycles()
+            t.find_duplicate_transactions()
+        } else {
+            Vec::new()
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn net_flow(account: Principal, start_ns: u64, end_ns: u64) -> i128 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.net_flow(account, start_ns, end_ns)
         } else {
             0
         }
     })
 }
+
 #[ic_cdk_macros::query]
-fn get_transaction_history() -> Vec<TransactionRecord> {
+fn have_transacted(a: Principal, b: Principal) -> bool {
     TOKEN_ICRC2.with(|token| {
         if let Some(t) = token.borrow().as_ref() {
-            t.get_transaction_history()
+            t.have_transacted(a, b)
+        } else {
+            false
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn fee_payer() -> FeePayer {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.fee_payer()
+        } else {
+            FeePayer::Sender
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_fee_payer(payer: FeePayer) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_fee_payer(payer)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn get_transactions_by_index(indices: Vec<u64>) -> Vec<Option<TransactionRecord>> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.get_transactions_by_index(&indices)
         } else {
             Vec::new()
         }
     })
 }
+
+#[ic_cdk_macros::query]
+fn deflation_schedule() -> Option<DeflationSchedule> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.deflation_schedule()))
+}
+
+#[ic_cdk_macros::query]
+fn last_deflation_burn() -> u64 {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.last_deflation_burn()
+        } else {
+            0
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn set_deflation_schedule(schedule: Option<DeflationSchedule>) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_deflation_schedule(schedule)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn mint_hook() -> Option<Principal> {
+    TOKEN_ICRC2.with(|token| token.borrow().as_ref().and_then(|t| t.mint_hook()))
+}
+
+#[ic_cdk_macros::update]
+fn set_mint_hook(hook: Option<Principal>) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            t.set_mint_hook(hook)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::query]
+fn balance_value_in_icp(account: Principal) -> Result<u64, String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(t) = token.borrow().as_ref() {
+            t.balance_value_in_icp(account)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
+
+#[ic_cdk_macros::update]
+fn force_transfer(from: Principal, to: Principal, amount: u128, reason: String) -> Result<(), String> {
+    TOKEN_ICRC2.with(|token| {
+        if let Some(ref mut t) = token.borrow_mut().as_mut() {
+            let result = t.force_transfer(from, to, amount, reason);
+            t.log_failure("force_transfer", ic_cdk::caller(), result)
+        } else {
+            Err("Token not initialized".to_string())
+        }
+    })
+}
@@ -0,0 +1,89 @@
+// Message context the ledger reads, and the one-way calls it makes. Unit tests
+// run outside a canister, where the system calls behind these are unavailable,
+// so under cfg(test) they read values the test sets and record outgoing calls.
+
+use candid::Principal;
+
+// on_mint arguments: (recipient, amount credited, index of the mint record)
+pub type MintHookArgs = (Principal, u128, u64);
+
+#[cfg(not(test))]
+pub fn caller() -> Principal {
+    ic_cdk::caller()
+}
+
+#[cfg(not(test))]
+pub fn time() -> u64 {
+    ic_cdk::api::time()
+}
+
+#[cfg(not(test))]
+pub fn canister_id() -> Principal {
+    ic_cdk::id()
+}
+
+// Instructions executed so far in the current message
+#[cfg(not(test))]
+pub fn instruction_counter() -> u64 {
+    ic_cdk::api::performance_counter(0)
+}
+
+// Fire-and-forget on_mint call; a hook that can't be reached is skipped
+#[cfg(not(test))]
+pub fn notify_mint_hook(hook: Principal, args: MintHookArgs) {
+    let _ = ic_cdk::notify(hook, "on_mint", args);
+}
+
+#[cfg(test)]
+pub use mock::*;
+
+#[cfg(test)]
+mod mock {
+    use super::MintHookArgs;
+    use candid::Principal;
+    use std::cell::{Cell, RefCell};
+
+    thread_local! {
+        static CALLER: Cell<Principal> = const { Cell::new(Principal::anonymous()) };
+        static TIME: Cell<u64> = const { Cell::new(0) };
+        static INSTRUCTIONS: Cell<u64> = const { Cell::new(0) };
+        static MINT_NOTIFICATIONS: RefCell<Vec<(Principal, MintHookArgs)>> = const { RefCell::new(Vec::new()) };
+    }
+
+    pub fn caller() -> Principal {
+        CALLER.with(Cell::get)
+    }
+
+    pub fn time() -> u64 {
+        TIME.with(Cell::get)
+    }
+
+    pub fn canister_id() -> Principal {
+        Principal::management_canister()
+    }
+
+    pub fn instruction_counter() -> u64 {
+        INSTRUCTIONS.with(Cell::get)
+    }
+
+    pub fn notify_mint_hook(hook: Principal, args: MintHookArgs) {
+        MINT_NOTIFICATIONS.with(|n| n.borrow_mut().push((hook, args)));
+    }
+
+    // Notifications sent since the last call, oldest first
+    pub fn take_mint_notifications() -> Vec<(Principal, MintHookArgs)> {
+        MINT_NOTIFICATIONS.with(|n| n.take())
+    }
+
+    pub fn set_caller(caller: Principal) {
+        CALLER.with(|c| c.set(caller));
+    }
+
+    pub fn set_time(time: u64) {
+        TIME.with(|t| t.set(time));
+    }
+
+    pub fn set_instruction_counter(count: u64) {
+        INSTRUCTIONS.with(|i| i.set(count));
+    }
+}
@@ -0,0 +1,461 @@
+use crate::amm;
+use super::{
+    DeflationSchedule, FailedOp, FeatureFlags, FeePayer, MintProposal, PauseMode, QueuedTransfer, RateLimit, RoundingMode,
+    StakeInfo, TimeLock, TokenICRC2, TransactionRecord, TxKind,
+};
+use candid::{CandidType, Deserialize, Principal};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// Snapshot layout up to STATE_VERSION 4, when amounts were nat64. Candid
+// won't decode nat64 into nat, so these mirror the old types field for field
+// and convert into the current ones after decoding.
+//
+// Candid also won't default a missing field: a record lacking one fails to
+// decode, and the opt around the state quietly turns it into null. Those
+// versions added fields without a bump, so everything introduced after v1 is
+// opt here and falls back to the TokenICRC2 default when absent.
+
+#[derive(CandidType, Deserialize)]
+pub struct TransactionRecordV4 {
+    from: Principal,
+    to: Principal,
+    amount: u64,
+    post_balance_from: u64,
+    post_balance_to: u64,
+    cycles_burnt: u64,
+    reason: String,
+    kind: Option<TxKind>,
+    timestamp: Option<u64>,
+    memo: Option<Vec<u8>>,
+    fee: Option<u64>,
+    caller: Option<Principal>,
+    authorized_by: Option<Principal>,
+}
+
+impl From<TransactionRecordV4> for TransactionRecord {
+    fn from(r: TransactionRecordV4) -> Self {
+        Self {
+            from: r.from,
+            to: r.to,
+            amount: r.amount.into(),
+            post_balance_from: r.post_balance_from.into(),
+            post_balance_to: r.post_balance_to.into(),
+            cycles_burnt: r.cycles_burnt,
+            reason: r.reason,
+            kind: r.kind.unwrap_or_default(),
+            timestamp: r.timestamp.unwrap_or(0),
+            memo: r.memo,
+            fee: r.fee.unwrap_or(0).into(),
+            caller: r.caller,
+            authorized_by: r.authorized_by,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TimeLockV4 {
+    amount: u64,
+    unlock_at: u64,
+}
+
+impl From<TimeLockV4> for TimeLock {
+    fn from(l: TimeLockV4) -> Self {
+        Self { amount: l.amount.into(), unlock_at: l.unlock_at }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct MintProposalV4 {
+    id: u64,
+    to: Principal,
+    amount: u64,
+    proposer: Principal,
+    approvals: Vec<Principal>,
+    expires_at: u64,
+}
+
+impl From<MintProposalV4> for MintProposal {
+    fn from(p: MintProposalV4) -> Self {
+        Self {
+            id: p.id,
+            to: p.to,
+            amount: p.amount.into(),
+            proposer: p.proposer,
+            approvals: p.approvals,
+            expires_at: p.expires_at,
+            custody_ref: None,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TokenICRC2V4 {
+    balances: HashMap<Principal, u64>,
+    allowances: HashMap<Principal, HashMap<Principal, u64>>,
+    allowance_expiries: Option<HashMap<(Principal, Principal), u64>>,
+    minters: HashSet<Principal>,
+    max_minters: Option<u32>,
+    owner: Principal,
+    total_supply: u64,
+    decimals: u8,
+    name: String,
+    symbol: String,
+    burnt_cycles: u64,
+    transaction_history: Vec<TransactionRecordV4>,
+    account_index: Option<HashMap<Principal, Vec<u64>>>,
+    held: HashMap<Principal, u64>,
+    time_locks: Option<HashMap<Principal, Vec<TimeLockV4>>>,
+    rounding_mode: Option<RoundingMode>,
+    max_allowance: Option<u64>,
+    locked_until: Option<HashMap<Principal, u64>>,
+    hide_frozen_balances: Option<bool>,
+    fee_collector: Option<Principal>,
+    existential_deposit: Option<u64>,
+    burn_account: Option<Principal>,
+    burned_total: Option<u64>,
+    large_burn_threshold: Option<u64>,
+    pending_burns: Option<HashMap<Principal, u64>>,
+    inactivity_period_ns: Option<u64>,
+    pending_reclaims: Option<HashMap<Principal, Principal>>,
+    accept_direct_deposits: Option<bool>,
+    mint_threshold: Option<u32>,
+    mint_fee_bps: Option<u16>,
+    mint_proposal_ttl_ns: Option<u64>,
+    mint_proposals: Option<HashMap<u64, MintProposalV4>>,
+    next_proposal_id: Option<u64>,
+    failed_operations: Option<VecDeque<FailedOp>>,
+    error_counts: Option<HashMap<String, u64>>,
+    require_memo: Option<bool>,
+    max_tx_amount: Option<u64>,
+    exempt_accounts: Option<HashSet<Principal>>,
+    require_recipient_whitelist: Option<bool>,
+    recipient_whitelist: Option<HashSet<Principal>>,
+    trading_enabled: Option<bool>,
+    trading_whitelist: Option<HashSet<Principal>>,
+    max_wallet: Option<u64>,
+    fee: Option<u64>,
+    approve_fee: Option<u64>,
+    transfer_from_fee: Option<u64>,
+    price_oracle: Option<Principal>,
+    fiat_fee: Option<u64>,
+    cached_price: Option<(u64, u64)>,
+    price_ttl_ns: Option<u64>,
+    tip_hash: Option<[u8; 32]>,
+    spend_authorizers: Option<HashMap<Principal, Principal>>,
+    rate_limit: Option<RateLimit>,
+    recent_ops: Option<HashMap<Principal, VecDeque<u64>>>,
+    feature_flags: Option<FeatureFlagsV5>,
+    maintenance_interval_ns: Option<u64>,
+}
+
+fn amount_map(m: HashMap<Principal, u64>) -> HashMap<Principal, u128> {
+    m.into_iter().map(|(k, v)| (k, v.into())).collect()
+}
+
+impl From<TokenICRC2V4> for TokenICRC2 {
+    fn from(l: TokenICRC2V4) -> Self {
+        // Built for the snapshot's owner, so owner-relative defaults such as
+        // the fee collector point at the right account
+        let base = TokenICRC2::new(l.owner, 0, l.decimals, String::new(), String::new());
+        Self {
+            balances: amount_map(l.balances),
+            allowances: l.allowances.into_iter().map(|(owner, spenders)| (owner, amount_map(spenders))).collect(),
+            allowance_expiries: l.allowance_expiries.unwrap_or_default(),
+            minters: l.minters,
+            max_minters: l.max_minters,
+            owner: l.owner,
+            total_supply: l.total_supply.into(),
+            decimals: l.decimals,
+            name: l.name,
+            symbol: l.symbol,
+            burnt_cycles: l.burnt_cycles,
+            transaction_history: l.transaction_history.into_iter().map(Into::into).collect(),
+            account_index: l.account_index.unwrap_or_default(),
+            held: amount_map(l.held),
+            time_locks: l
+                .time_locks
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(account, locks)| (account, locks.into_iter().map(Into::into).collect()))
+                .collect(),
+            rounding_mode: l.rounding_mode.unwrap_or(base.rounding_mode),
+            max_allowance: l.max_allowance.map(u128::from),
+            locked_until: l.locked_until.unwrap_or_default(),
+            hide_frozen_balances: l.hide_frozen_balances.unwrap_or(base.hide_frozen_balances),
+            fee_collector: l.fee_collector.unwrap_or(base.fee_collector),
+            existential_deposit: l.existential_deposit.map_or(base.existential_deposit, u128::from),
+            burn_account: l.burn_account.unwrap_or(base.burn_account),
+            burned_total: l.burned_total.map_or(base.burned_total, u128::from),
+            large_burn_threshold: l.large_burn_threshold.map(u128::from),
+            pending_burns: amount_map(l.pending_burns.unwrap_or_default()),
+            inactivity_period_ns: l.inactivity_period_ns,
+            pending_reclaims: l.pending_reclaims.unwrap_or_default(),
+            accept_direct_deposits: l.accept_direct_deposits.unwrap_or(base.accept_direct_deposits),
+            mint_threshold: l.mint_threshold.unwrap_or(base.mint_threshold),
+            mint_fee_bps: l.mint_fee_bps.unwrap_or(base.mint_fee_bps),
+            mint_proposal_ttl_ns: l.mint_proposal_ttl_ns.unwrap_or(base.mint_proposal_ttl_ns),
+            mint_proposals: l.mint_proposals.unwrap_or_default().into_iter().map(|(id, p)| (id, p.into())).collect(),
+            next_proposal_id: l.next_proposal_id.unwrap_or(base.next_proposal_id),
+            failed_operations: l.failed_operations.unwrap_or_default(),
+            error_counts: l.error_counts.unwrap_or_default(),
+            require_memo: l.require_memo.unwrap_or(base.require_memo),
+            max_tx_amount: l.max_tx_amount.map(u128::from),
+            exempt_accounts: l.exempt_accounts.unwrap_or_default(),
+            require_recipient_whitelist: l.require_recipient_whitelist.unwrap_or(base.require_recipient_whitelist),
+            recipient_whitelist: l.recipient_whitelist.unwrap_or_default(),
+            trading_enabled: l.trading_enabled.unwrap_or(base.trading_enabled),
+            trading_whitelist: l.trading_whitelist.unwrap_or_default(),
+            max_wallet: l.max_wallet.map(u128::from),
+            fee: l.fee.map_or(base.fee, u128::from),
+            approve_fee: l.approve_fee.map_or(base.approve_fee, u128::from),
+            transfer_from_fee: l.transfer_from_fee.map(u128::from),
+            price_oracle: l.price_oracle,
+            fiat_fee: l.fiat_fee,
+            cached_price: l.cached_price,
+            price_ttl_ns: l.price_ttl_ns.unwrap_or(base.price_ttl_ns),
+            tip_hash: l.tip_hash,
+            spend_authorizers: l.spend_authorizers.unwrap_or_default(),
+            rate_limit: l.rate_limit,
+            recent_ops: l.recent_ops.unwrap_or_default(),
+            feature_flags: l.feature_flags.map_or(base.feature_flags, FeatureFlags::from),
+            maintenance_interval_ns: l.maintenance_interval_ns,
+            // Fields added since v4 were never in these snapshots
+            ..base
+        }
+    }
+}
+
+// force_transfer came after the v5 bump
+#[derive(CandidType, Deserialize)]
+pub struct FeatureFlagsV5 {
+    transfer: bool,
+    approve: bool,
+    transfer_from: bool,
+    mint: bool,
+    burn: bool,
+    force_transfer: Option<bool>,
+}
+
+impl From<FeatureFlagsV5> for FeatureFlags {
+    fn from(l: FeatureFlagsV5) -> Self {
+        Self {
+            transfer: l.transfer,
+            approve: l.approve,
+            transfer_from: l.transfer_from,
+            mint: l.mint,
+            burn: l.burn,
+            force_transfer: l.force_transfer.unwrap_or(false),
+        }
+    }
+}
+
+// auto_compound came after the v5 bump
+#[derive(CandidType, Deserialize)]
+pub struct StakeInfoV5 {
+    amount: u128,
+    since: u64,
+    accrued: u128,
+    auto_compound: Option<bool>,
+}
+
+impl From<StakeInfoV5> for StakeInfo {
+    fn from(l: StakeInfoV5) -> Self {
+        Self { amount: l.amount, since: l.since, accrued: l.accrued, auto_compound: l.auto_compound.unwrap_or(false) }
+    }
+}
+
+// Snapshot layout for STATE_VERSION 5. Fields were added after the v5 bump
+// without another one, so a v5 snapshot may lack any of them: those are opt
+// here. Everything else is laid out as at the bump.
+#[derive(CandidType, Deserialize)]
+pub struct TokenICRC2V5 {
+    balances: HashMap<Principal, u128>,
+    allowances: HashMap<Principal, HashMap<Principal, u128>>,
+    allowance_expiries: HashMap<(Principal, Principal), u64>,
+    minters: HashSet<Principal>,
+    max_minters: Option<u32>,
+    owner: Principal,
+    total_supply: u128,
+    decimals: u8,
+    name: String,
+    symbol: String,
+    burnt_cycles: u64,
+    transaction_history: Vec<TransactionRecord>,
+    account_index: HashMap<Principal, Vec<u64>>,
+    held: HashMap<Principal, u128>,
+    time_locks: HashMap<Principal, Vec<TimeLock>>,
+    rounding_mode: RoundingMode,
+    max_allowance: Option<u128>,
+    locked_until: HashMap<Principal, u64>,
+    hide_frozen_balances: bool,
+    fee_collector: Principal,
+    existential_deposit: u128,
+    burn_account: Principal,
+    burned_total: u128,
+    large_burn_threshold: Option<u128>,
+    pending_burns: HashMap<Principal, u128>,
+    inactivity_period_ns: Option<u64>,
+    pending_reclaims: HashMap<Principal, Principal>,
+    accept_direct_deposits: bool,
+    mint_threshold: u32,
+    mint_fee_bps: u16,
+    mint_proposal_ttl_ns: u64,
+    mint_proposals: HashMap<u64, MintProposal>,
+    next_proposal_id: u64,
+    failed_operations: VecDeque<FailedOp>,
+    error_counts: HashMap<String, u64>,
+    require_memo: bool,
+    max_tx_amount: Option<u128>,
+    exempt_accounts: HashSet<Principal>,
+    require_recipient_whitelist: bool,
+    recipient_whitelist: HashSet<Principal>,
+    trading_enabled: bool,
+    trading_whitelist: HashSet<Principal>,
+    max_wallet: Option<u128>,
+    fee: u128,
+    approve_fee: u128,
+    transfer_from_fee: Option<u128>,
+    price_oracle: Option<Principal>,
+    fiat_fee: Option<u64>,
+    cached_price: Option<(u64, u64)>,
+    price_ttl_ns: u64,
+    tip_hash: Option<[u8; 32]>,
+    spend_authorizers: HashMap<Principal, Principal>,
+    rate_limit: Option<RateLimit>,
+    recent_ops: HashMap<Principal, VecDeque<u64>>,
+    feature_flags: FeatureFlagsV5,
+    maintenance_interval_ns: Option<u64>,
+    // Added after the v5 bump
+    user_registry: Option<Principal>,
+    registered_at: Option<HashMap<Principal, u64>>,
+    fee_free_below: Option<u128>,
+    require_custody_ref: Option<bool>,
+    stakes: Option<HashMap<Principal, StakeInfoV5>>,
+    staking_rate_bps: Option<u16>,
+    memo_uniqueness_window_ns: Option<u64>,
+    pause_mode: Option<PauseMode>,
+    queued_transfers: Option<VecDeque<QueuedTransfer>>,
+    icp_ledger: Option<Principal>,
+    icp_fee: Option<u128>,
+    pool_fee_bps: Option<u16>,
+    token_reserve: Option<u128>,
+    icp_reserve: Option<u128>,
+    lp_shares: Option<HashMap<Principal, u128>>,
+    total_lp_shares: Option<u128>,
+    icp_owed: Option<HashMap<Principal, u128>>,
+    min_approval_amount: Option<u128>,
+    fee_payer: Option<FeePayer>,
+    deflation: Option<DeflationSchedule>,
+    last_deflation_burn: Option<u64>,
+    mint_hook: Option<Principal>,
+    event_log_capacity: Option<u64>,
+    dropped_events: Option<u64>,
+    minter_granted_at: Option<HashMap<Principal, u64>>,
+    minter_activation_delay_ns: Option<u64>,
+}
+
+impl From<TokenICRC2V5> for TokenICRC2 {
+    fn from(l: TokenICRC2V5) -> Self {
+        let base = TokenICRC2::new(l.owner, 0, l.decimals, String::new(), String::new());
+        let stakes: HashMap<Principal, StakeInfo> =
+            l.stakes.unwrap_or_default().into_iter().map(|(p, s)| (p, s.into())).collect();
+        // v5 paid every stake a flat rate; the emission that pays the same
+        // now is that rate on the total staked
+        let staked = stakes.values().fold(0u128, |sum, s| sum.saturating_add(s.amount));
+        let staking_emission = amm::mul_div(staked, l.staking_rate_bps.unwrap_or(0) as u128, 10_000).unwrap_or(0);
+        // v5 queued transfers while the transfer flag was off; that is a pause now
+        let pause_mode = l.pause_mode.unwrap_or_default();
+        let mut feature_flags: FeatureFlags = l.feature_flags.into();
+        let mut paused_operations = HashSet::new();
+        if pause_mode == PauseMode::Queue && !feature_flags.transfer {
+            feature_flags.transfer = true;
+            paused_operations.insert(TxKind::Transfer);
+        }
+        Self {
+            balances: l.balances,
+            allowances: l.allowances,
+            allowance_expiries: l.allowance_expiries,
+            minters: l.minters,
+            max_minters: l.max_minters,
+            owner: l.owner,
+            total_supply: l.total_supply,
+            decimals: l.decimals,
+            name: l.name,
+            symbol: l.symbol,
+            burnt_cycles: l.burnt_cycles,
+            transaction_history: l.transaction_history,
+            account_index: l.account_index,
+            held: l.held,
+            time_locks: l.time_locks,
+            rounding_mode: l.rounding_mode,
+            max_allowance: l.max_allowance,
+            locked_until: l.locked_until,
+            hide_frozen_balances: l.hide_frozen_balances,
+            fee_collector: l.fee_collector,
+            existential_deposit: l.existential_deposit,
+            burn_account: l.burn_account,
+            burned_total: l.burned_total,
+            large_burn_threshold: l.large_burn_threshold,
+            pending_burns: l.pending_burns,
+            inactivity_period_ns: l.inactivity_period_ns,
+            pending_reclaims: l.pending_reclaims,
+            accept_direct_deposits: l.accept_direct_deposits,
+            mint_threshold: l.mint_threshold,
+            mint_fee_bps: l.mint_fee_bps,
+            mint_proposal_ttl_ns: l.mint_proposal_ttl_ns,
+            mint_proposals: l.mint_proposals,
+            next_proposal_id: l.next_proposal_id,
+            failed_operations: l.failed_operations,
+            error_counts: l.error_counts,
+            require_memo: l.require_memo,
+            max_tx_amount: l.max_tx_amount,
+            exempt_accounts: l.exempt_accounts,
+            require_recipient_whitelist: l.require_recipient_whitelist,
+            recipient_whitelist: l.recipient_whitelist,
+            trading_enabled: l.trading_enabled,
+            trading_whitelist: l.trading_whitelist,
+            max_wallet: l.max_wallet,
+            fee: l.fee,
+            approve_fee: l.approve_fee,
+            transfer_from_fee: l.transfer_from_fee,
+            price_oracle: l.price_oracle,
+            fiat_fee: l.fiat_fee,
+            cached_price: l.cached_price,
+            price_ttl_ns: l.price_ttl_ns,
+            tip_hash: l.tip_hash,
+            spend_authorizers: l.spend_authorizers,
+            rate_limit: l.rate_limit,
+            recent_ops: l.recent_ops,
+            feature_flags,
+            paused_operations,
+            maintenance_interval_ns: l.maintenance_interval_ns,
+            user_registry: l.user_registry,
+            registered_at: l.registered_at.unwrap_or_default(),
+            fee_free_below: l.fee_free_below.unwrap_or(base.fee_free_below),
+            require_custody_ref: l.require_custody_ref.unwrap_or(base.require_custody_ref),
+            stakes,
+            staking_emission,
+            memo_uniqueness_window_ns: l.memo_uniqueness_window_ns,
+            pause_mode,
+            queued_transfers: l.queued_transfers.unwrap_or_default(),
+            icp_ledger: l.icp_ledger,
+            icp_fee: l.icp_fee.unwrap_or(base.icp_fee),
+            pool_fee_bps: l.pool_fee_bps.unwrap_or(base.pool_fee_bps),
+            token_reserve: l.token_reserve.unwrap_or(0),
+            icp_reserve: l.icp_reserve.unwrap_or(0),
+            lp_shares: l.lp_shares.unwrap_or_default(),
+            total_lp_shares: l.total_lp_shares.unwrap_or(0),
+            icp_owed: l.icp_owed.unwrap_or_default(),
+            min_approval_amount: l.min_approval_amount.unwrap_or(base.min_approval_amount),
+            fee_payer: l.fee_payer.unwrap_or_default(),
+            deflation: l.deflation,
+            last_deflation_burn: l.last_deflation_burn.unwrap_or(0),
+            mint_hook: l.mint_hook,
+            event_log_capacity: l.event_log_capacity.unwrap_or(base.event_log_capacity),
+            dropped_events: l.dropped_events.unwrap_or(0),
+            // Minters granted before tracking have no entry, and so are active at once
+            minter_granted_at: l.minter_granted_at.unwrap_or_default(),
+            minter_activation_delay_ns: l.minter_activation_delay_ns.unwrap_or(0),
+        }
+    }
+}
@@ -0,0 +1,2043 @@
+use super::*;
+
+fn owner() -> Principal {
+    Principal::from_slice(&[1])
+}
+
+fn ledger() -> TokenICRC2 {
+    env::set_caller(owner());
+    env::set_time(1_000);
+    TokenICRC2::new(owner(), 1_000_000, 8, "Token".to_string(), "TKN".to_string())
+}
+
+fn with_rounding(mode: RoundingMode) -> TokenICRC2 {
+    let mut t = ledger();
+    t.set_rounding_mode(mode).unwrap();
+    t
+}
+
+#[test]
+fn rounding_down_truncates_the_portion() {
+    let t = with_rounding(RoundingMode::Down);
+    assert_eq!(t.bps_portion(12_345, 250), 308);
+    assert_eq!(t.bps_portion(3, 5_000), 1);
+    assert_eq!(t.bps_portion(10_000, 250), 250);
+}
+
+#[test]
+fn rounding_up_takes_any_remainder() {
+    let t = with_rounding(RoundingMode::Up);
+    assert_eq!(t.bps_portion(12_345, 250), 309);
+    assert_eq!(t.bps_portion(3, 5_000), 2);
+    assert_eq!(t.bps_portion(10_000, 250), 250);
+}
+
+#[test]
+fn rounding_nearest_rounds_halves_up() {
+    let t = with_rounding(RoundingMode::Nearest);
+    assert_eq!(t.bps_portion(12_345, 250), 309);
+    assert_eq!(t.bps_portion(12_301, 250), 308);
+    assert_eq!(t.bps_portion(3, 5_000), 2);
+    assert_eq!(t.bps_portion(10_000, 250), 250);
+}
+
+#[test]
+fn bps_portion_does_not_overflow_at_the_maximum_amount() {
+    let t = with_rounding(RoundingMode::Up);
+    assert_eq!(t.bps_portion(u128::MAX, 10_000), u128::MAX);
+    assert_eq!(t.bps_portion(u128::MAX, 0), 0);
+}
+
+// The state layout pre_upgrade wrote at STATE_VERSION 1
+#[derive(CandidType)]
+struct RecordV1 {
+    from: Principal,
+    to: Principal,
+    amount: u64,
+    post_balance_from: u64,
+    post_balance_to: u64,
+    cycles_burnt: u64,
+    reason: String,
+}
+
+#[derive(CandidType)]
+struct TokenV1 {
+    balances: HashMap<Principal, u64>,
+    allowances: HashMap<Principal, HashMap<Principal, u64>>,
+    minters: HashSet<Principal>,
+    owner: Principal,
+    total_supply: u64,
+    decimals: u8,
+    name: String,
+    symbol: String,
+    burnt_cycles: u64,
+    transaction_history: Vec<RecordV1>,
+    held: HashMap<Principal, u64>,
+    rounding_mode: RoundingMode,
+    max_allowance: Option<u64>,
+}
+
+fn user(n: u8) -> Principal {
+    Principal::from_slice(&[n, 0xAA])
+}
+
+// Stable memory is read whole, so a snapshot is followed by zero padding
+fn stable_image(args: Vec<u8>) -> Vec<u8> {
+    let mut image = args;
+    image.resize(image.len() + 4096, 0);
+    image
+}
+
+fn v1_snapshot() -> Vec<u8> {
+    let state = TokenV1 {
+        balances: HashMap::from([(owner(), 700), (user(2), 300)]),
+        allowances: HashMap::from([(owner(), HashMap::from([(user(2), 50)]))]),
+        minters: HashSet::from([owner()]),
+        owner: owner(),
+        total_supply: 1_000,
+        decimals: 8,
+        name: "Token".to_string(),
+        symbol: "TKN".to_string(),
+        burnt_cycles: 42,
+        transaction_history: vec![
+            RecordV1 {
+                from: owner(),
+                to: owner(),
+                amount: 1_000,
+                post_balance_from: 0,
+                post_balance_to: 1_000,
+                cycles_burnt: 0,
+                reason: "Minting operation has no cycle burn cost.".to_string(),
+            },
+            RecordV1 {
+                from: owner(),
+                to: user(2),
+                amount: 300,
+                post_balance_from: 700,
+                post_balance_to: 300,
+                cycles_burnt: 42,
+                reason: "Transfer".to_string(),
+            },
+        ],
+        held: HashMap::new(),
+        rounding_mode: RoundingMode::Up,
+        max_allowance: Some(500),
+    };
+    stable_image(candid::encode_args((1u32, Some(state))).unwrap())
+}
+
+#[test]
+fn v1_snapshot_restores_with_its_balances_and_history() {
+    let t = restore_state(&v1_snapshot()).expect("v1 state was dropped");
+    assert_eq!(t.get_owner(), owner());
+    assert_eq!(t.balance_of(owner()), 700);
+    assert_eq!(t.balance_of(user(2)), 300);
+    assert_eq!(t.allowance(owner(), user(2)), 50);
+    assert_eq!(t.total_supply(), 1_000);
+    assert_eq!(t.rounding_mode(), RoundingMode::Up);
+    assert_eq!(t.max_allowance(), Some(500));
+    assert_eq!(t.burnt_cycles(), 42);
+    let history = t.get_transaction_history();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].kind, TxKind::Mint);
+    assert_eq!(history[1].kind, TxKind::Transfer);
+    // Rebuilt by migrate_state for snapshots without them
+    assert_eq!(t.account_tx_count(user(2)), 1);
+    assert_eq!(t.tip().map(|(index, _)| index), Some(1));
+    // Fields v1 lacked take their defaults
+    assert_eq!(t.feature_flags(), FeatureFlags::default());
+    assert_eq!(t.fee_collector(), owner());
+}
+
+#[test]
+fn current_snapshot_round_trips() {
+    let mut t = ledger();
+    t.set_rounding_mode(RoundingMode::Nearest).unwrap();
+    let image = stable_image(candid::encode_args((STATE_VERSION, Some(&t))).unwrap());
+    let restored = restore_state(&image).expect("current state was dropped");
+    assert_eq!(restored.balance_of(owner()), 1_000_000);
+    assert_eq!(restored.rounding_mode(), RoundingMode::Nearest);
+}
+
+#[test]
+fn empty_snapshot_restores_as_uninitialized() {
+    let image = stable_image(candid::encode_args((STATE_VERSION, None::<TokenICRC2>)).unwrap());
+    assert!(restore_state(&image).is_none());
+}
+
+#[test]
+#[should_panic(expected = "does not decode")]
+fn undecodable_snapshot_traps_instead_of_wiping_state() {
+    // A v1 layout labelled as current can't decode into TokenICRC2
+    let state = TokenV1 {
+        balances: HashMap::new(),
+        allowances: HashMap::new(),
+        minters: HashSet::new(),
+        owner: owner(),
+        total_supply: 0,
+        decimals: 8,
+        name: String::new(),
+        symbol: String::new(),
+        burnt_cycles: 0,
+        transaction_history: Vec::new(),
+        held: HashMap::new(),
+        rounding_mode: RoundingMode::Down,
+        max_allowance: None,
+    };
+    restore_state(&stable_image(candid::encode_args((STATE_VERSION, Some(state))).unwrap()));
+}
+
+// `ty` without the named fields in any record it contains
+fn strip_fields(ty: &candid::types::Type, ids: &[u32]) -> candid::types::Type {
+    use candid::types::{internal::find_type, Field, TypeInner};
+    let strip_all = |fields: &[Field], drop: bool| -> Vec<Field> {
+        fields
+            .iter()
+            .filter(|f| !(drop && ids.contains(&f.id.get_id())))
+            .map(|f| Field { id: f.id.clone(), ty: strip_fields(&f.ty, ids) })
+            .collect()
+    };
+    match ty.as_ref() {
+        TypeInner::Knot(id) => strip_fields(&find_type(id).expect("unregistered type"), ids),
+        TypeInner::Opt(inner) => TypeInner::Opt(strip_fields(inner, ids)).into(),
+        TypeInner::Vec(inner) => TypeInner::Vec(strip_fields(inner, ids)).into(),
+        TypeInner::Record(fields) => TypeInner::Record(strip_all(fields, true)).into(),
+        TypeInner::Variant(fields) => TypeInner::Variant(strip_all(fields, false)).into(),
+        _ => ty.clone(),
+    }
+}
+
+// A v5-labelled snapshot of `t` as a build that lacked `fields` wrote it
+fn v5_snapshot_without(t: &TokenICRC2, fields: &[&str]) -> Vec<u8> {
+    let ids: Vec<u32> = fields.iter().map(|f| candid::idl_hash(f)).collect();
+    let env = candid::types::TypeEnv::new();
+    let types = [u32::ty(), strip_fields(&Option::<TokenICRC2>::ty(), &ids)];
+    let bytes = candid::encode_args((5u32, Some(t))).unwrap();
+    let args = candid::IDLArgs::from_bytes_with_types(&bytes, &env, &types).unwrap();
+    stable_image(args.to_bytes_with_types(&env, &types).unwrap())
+}
+
+// v5_snapshot_without, plus a field of the v5 layout that the current one dropped
+fn v5_snapshot_with_field(t: &TokenICRC2, fields: &[&str], name: &str, value: candid::IDLValue) -> Vec<u8> {
+    use candid::types::{Field, Label, TypeInner};
+    use candid::{IDLArgs, IDLValue};
+    let env = candid::types::TypeEnv::new();
+    let ids: Vec<u32> = fields.iter().map(|f| candid::idl_hash(f)).collect();
+    let state_ty = strip_fields(&Option::<TokenICRC2>::ty(), &ids);
+    let bytes = candid::encode_args((5u32, Some(t))).unwrap();
+    let mut args = IDLArgs::from_bytes_with_types(&bytes, &env, &[u32::ty(), state_ty.clone()]).unwrap();
+    let (TypeInner::Opt(record_ty), IDLValue::Opt(record)) = (state_ty.as_ref(), &mut args.args[1]) else {
+        panic!("state is not an opt record");
+    };
+    let (TypeInner::Record(field_tys), IDLValue::Record(values)) = (record_ty.as_ref(), record.as_mut()) else {
+        panic!("state is not an opt record");
+    };
+    let mut field_tys = field_tys.clone();
+    field_tys.push(Field { id: Label::Named(name.to_string()).into(), ty: value.value_ty() });
+    field_tys.sort_by_key(|f| f.id.get_id());
+    values.push(candid::types::value::IDLField { id: Label::Named(name.to_string()), val: value });
+    values.sort_by_key(|f| f.id.get_id());
+    let types = [u32::ty(), TypeInner::Opt(TypeInner::Record(field_tys).into()).into()];
+    stable_image(args.to_bytes_with_types(&env, &types).unwrap())
+}
+
+// Current fields a v5 snapshot may lack, all added after the bump
+const POST_V5_FIELDS: &[&str] = &[
+    "user_registry", "registered_at", "fee_free_below", "require_custody_ref", "stakes", "staking_emission",
+    "memo_uniqueness_window_ns", "pause_mode", "queued_transfers", "icp_ledger", "icp_fee", "pool_fee_bps",
+    "token_reserve", "icp_reserve", "lp_shares", "total_lp_shares", "icp_owed", "min_approval_amount", "fee_payer",
+    "deflation", "last_deflation_burn", "mint_hook", "event_log_capacity", "dropped_events", "minter_granted_at",
+    "minter_activation_delay_ns", "paused_operations",
+];
+
+#[test]
+fn v5_snapshot_predating_later_fields_restores() {
+    let mut t = ledger();
+    t.set_rounding_mode(RoundingMode::Up).unwrap();
+    let restored = restore_state(&v5_snapshot_without(&t, POST_V5_FIELDS)).expect("v5 state was dropped");
+    assert_eq!(restored.balance_of(owner()), 1_000_000);
+    assert_eq!(restored.rounding_mode(), RoundingMode::Up);
+    assert_eq!(restored.user_registry, None);
+    assert!(restored.registered_at.is_empty());
+    assert_eq!(restored.fee_free_below(), 0);
+    assert!(!restored.require_custody_ref());
+    assert!(restored.stakes.is_empty());
+    assert_eq!(restored.staking_emission(), 0);
+    assert_eq!(restored.memo_uniqueness_window_ns, None);
+    assert_eq!(restored.pause_mode(), PauseMode::Reject);
+    assert!(restored.queued_transfers.is_empty());
+    assert_eq!(restored.icp_ledger, None);
+    assert_eq!((restored.icp_fee, restored.pool_fee_bps), (10_000, 30));
+    assert_eq!((restored.token_reserve, restored.icp_reserve, restored.total_lp_shares), (0, 0, 0));
+    assert!(restored.lp_shares.is_empty() && restored.icp_owed.is_empty());
+    assert_eq!(restored.min_approval_amount(), 0);
+    assert_eq!(restored.fee_payer(), FeePayer::Sender);
+    assert_eq!((restored.deflation, restored.last_deflation_burn), (None, 0));
+    assert_eq!(restored.mint_hook, None);
+    assert_eq!((restored.event_log_capacity(), restored.dropped_events()), (FAILED_OPS_CAPACITY, 0));
+    assert!(restored.minter_granted_at.is_empty());
+    assert_eq!(restored.minter_activation_delay(), 0);
+}
+
+#[test]
+fn v5_stake_predating_auto_compound_restores_as_manual() {
+    let mut t = ledger();
+    t.stake(owner(), 100).unwrap();
+    let restored = restore_state(&v5_snapshot_without(&t, &["auto_compound"])).expect("v5 state was dropped");
+    assert_eq!(restored.stakes.get(&owner()).map(|s| (s.amount, s.auto_compound)), Some((100, false)));
+}
+
+#[test]
+fn v5_flags_predating_force_transfer_restore_with_it_off() {
+    let mut t = ledger();
+    t.feature_flags.mint = false;
+    t.feature_flags.force_transfer = true;
+    let restored = restore_state(&v5_snapshot_without(&t, &["force_transfer"])).expect("v5 state was dropped");
+    assert!(!restored.feature_flags.mint);
+    assert!(!restored.feature_flags.force_transfer);
+}
+
+#[test]
+fn v5_minters_predating_activation_tracking_stay_active() {
+    let mut t = ledger();
+    t.minters.insert(user(3));
+    let mut restored = restore_state(&v5_snapshot_without(&t, POST_V5_FIELDS)).expect("v5 state was dropped");
+    restored.set_minter_activation_delay(1_000_000).unwrap();
+    assert_eq!(restored.require_minter(user(3)), Ok(()));
+}
+
+#[test]
+fn v5_staking_rate_becomes_the_emission_paying_the_same() {
+    let mut t = ledger();
+    t.stake(owner(), 400_000).unwrap();
+    let image = v5_snapshot_with_field(&t, &["staking_emission"], "staking_rate_bps", candid::IDLValue::Nat16(500));
+    let restored = restore_state(&image).expect("v5 state was dropped");
+    assert_eq!(restored.staking_emission(), 20_000);
+    assert_eq!(restored.current_apy_bps(), 500);
+}
+
+#[test]
+fn v5_snapshot_keeps_fields_added_after_the_bump() {
+    let mut t = ledger();
+    t.user_registry = Some(user(9));
+    t.registered_at.insert(user(2), 77);
+    t.fee_free_below = 50;
+    t.require_custody_ref = true;
+    t.stake(owner(), 100).unwrap();
+    t.stakes.get_mut(&owner()).unwrap().auto_compound = true;
+    t.memo_uniqueness_window_ns = Some(60);
+    t.pause_mode = PauseMode::Queue;
+    t.queued_transfers.push_back(QueuedTransfer { from: owner(), to: user(3), amount: 40, memo: None, queued_at: 5 });
+    t.icp_ledger = Some(user(8));
+    t.icp_fee = 7;
+    t.pool_fee_bps = 45;
+    t.token_reserve = 300;
+    t.icp_reserve = 600;
+    t.lp_shares.insert(user(4), 20);
+    t.total_lp_shares = 20;
+    t.icp_owed.insert(user(5), 11);
+    t.min_approval_amount = 25;
+    t.fee_payer = FeePayer::Recipient;
+    t.deflation = Some(DeflationSchedule { rate_bps: 100, interval_ns: 9 });
+    t.last_deflation_burn = 33;
+    t.mint_hook = Some(user(6));
+    t.feature_flags.force_transfer = true;
+    t.event_log_capacity = 12;
+    t.dropped_events = 3;
+    t.minter_granted_at.insert(user(3), 44);
+    t.minter_activation_delay_ns = 900;
+    let image = stable_image(candid::encode_args((5u32, Some(&t))).unwrap());
+    let restored = restore_state(&image).expect("v5 state was dropped");
+    assert_eq!(restored.user_registry, Some(user(9)));
+    assert_eq!(restored.registered_at.get(&user(2)), Some(&77));
+    assert_eq!(restored.fee_free_below(), 50);
+    assert!(restored.require_custody_ref());
+    assert_eq!(restored.stakes.get(&owner()).map(|s| (s.amount, s.auto_compound)), Some((100, true)));
+    assert_eq!(restored.memo_uniqueness_window_ns, Some(60));
+    assert_eq!(restored.pause_mode(), PauseMode::Queue);
+    assert_eq!(restored.queued_transfers.front().map(|q| q.amount), Some(40));
+    assert_eq!(restored.icp_ledger, Some(user(8)));
+    assert_eq!((restored.icp_fee, restored.pool_fee_bps), (7, 45));
+    assert_eq!((restored.token_reserve, restored.icp_reserve), (300, 600));
+    assert_eq!((restored.lp_shares.get(&user(4)), restored.total_lp_shares), (Some(&20), 20));
+    assert_eq!(restored.icp_owed.get(&user(5)), Some(&11));
+    assert_eq!(restored.min_approval_amount(), 25);
+    assert_eq!(restored.fee_payer(), FeePayer::Recipient);
+    assert_eq!(restored.deflation, Some(DeflationSchedule { rate_bps: 100, interval_ns: 9 }));
+    assert_eq!(restored.last_deflation_burn, 33);
+    assert_eq!(restored.mint_hook, Some(user(6)));
+    assert!(restored.feature_flags.force_transfer);
+    assert_eq!((restored.event_log_capacity(), restored.dropped_events()), (12, 3));
+    assert_eq!(restored.minter_granted_at.get(&user(3)), Some(&44));
+    assert_eq!(restored.minter_activation_delay(), 900);
+}
+
+
+fn drained_from(spendable: u128) -> (u128, u128) {
+    let mut t = ledger();
+    t.set_fee(10).unwrap();
+    t.set_fee_free_below(100).unwrap();
+    t.transfer(owner(), user(1), spendable, None).unwrap();
+    let sent = t.transfer_all(user(1), user(2), None).unwrap();
+    (sent, t.balance_of(user(1)))
+}
+
+#[test]
+fn transfer_all_sends_a_balance_within_the_fee_free_band_whole() {
+    assert_eq!(drained_from(60), (60, 0));
+    assert_eq!(drained_from(100), (100, 0));
+}
+
+#[test]
+fn transfer_all_just_above_the_band_leaves_nothing_behind() {
+    assert_eq!(drained_from(105), (95, 0));
+    assert_eq!(drained_from(500), (490, 0));
+}
+
+#[test]
+fn transfer_and_hold_escrows_what_the_receiver_got() {
+    let mut t = ledger();
+    t.set_fee(10).unwrap();
+    assert_eq!(t.transfer_and_hold(owner(), user(7), 100), Ok(100));
+    assert_eq!((t.balance_of(user(7)), t.spendable_balance(user(7))), (100, 0));
+}
+
+#[test]
+fn transfer_and_hold_that_cannot_hold_moves_nothing() {
+    let mut t = ledger();
+    t.time_locks.entry(user(7)).or_default().push(TimeLock { amount: 1_000, unlock_at: u64::MAX });
+    assert!(t.transfer_and_hold(owner(), user(7), 100).is_err());
+    assert_eq!((t.balance_of(owner()), t.balance_of(user(7))), (1_000_000, 0));
+    assert!(t.held.is_empty());
+}
+
+// A ledger with every amount-denominated value set, each a multiple of 100
+fn ledger_with_every_amount() -> TokenICRC2 {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 5_000, None).unwrap();
+    t.allowances.entry(owner()).or_default().insert(user(2), 700);
+    t.held.insert(user(1), 300);
+    t.time_locks.entry(user(1)).or_default().push(TimeLock { amount: 200, unlock_at: u64::MAX });
+    t.stakes.insert(user(1), StakeInfo { amount: 400, since: 1_000, accrued: 100, auto_compound: false });
+    t.burned_total = 900;
+    t.existential_deposit = 100;
+    t.large_burn_threshold = Some(10_000);
+    t.pending_burns.insert(user(1), 20_000);
+    t.max_allowance = Some(50_000);
+    t.max_tx_amount = Some(60_000);
+    t.max_wallet = Some(70_000);
+    t.fee = 100;
+    t.fee_free_below = 200;
+    t.approve_fee = 300;
+    t.min_approval_amount = 400;
+    t.transfer_from_fee = Some(500);
+    t.token_reserve = 1_100;
+    t.queued_transfers.push_back(QueuedTransfer { from: user(1), to: user(2), amount: 600, memo: None, queued_at: 0 });
+    t.mint_proposals.insert(
+        1,
+        MintProposal {
+            id: 1,
+            to: user(3),
+            amount: 800,
+            proposer: owner(),
+            approvals: Vec::new(),
+            expires_at: u64::MAX,
+            custody_ref: None,
+        },
+    );
+    t
+}
+
+fn every_amount(t: &TokenICRC2) -> Vec<u128> {
+    let stake = t.stakes[&user(1)];
+    vec![
+        t.balance_of(owner()),
+        t.balance_of(user(1)),
+        t.allowance(owner(), user(2)),
+        t.held[&user(1)],
+        t.time_locks[&user(1)][0].amount,
+        stake.amount,
+        stake.accrued,
+        t.total_supply,
+        t.burned_total,
+        t.existential_deposit,
+        t.large_burn_threshold.unwrap(),
+        t.pending_burns[&user(1)],
+        t.max_allowance.unwrap(),
+        t.max_tx_amount.unwrap(),
+        t.max_wallet.unwrap(),
+        t.fee,
+        t.fee_free_below,
+        t.approve_fee,
+        t.min_approval_amount,
+        t.transfer_from_fee.unwrap(),
+        t.token_reserve,
+        t.queued_transfers[0].amount,
+        t.mint_proposals[&1].amount,
+    ]
+}
+
+#[test]
+fn redenominate_scales_every_amount_and_back() {
+    let mut t = ledger_with_every_amount();
+    let before = every_amount(&t);
+    t.redenominate(10, true).unwrap();
+    assert_eq!(every_amount(&t), before.iter().map(|v| v * 10).collect::<Vec<_>>());
+    assert_eq!(t.decimals, 8);
+    t.redenominate(10, false).unwrap();
+    assert_eq!(every_amount(&t), before);
+}
+
+#[test]
+fn redenominate_drops_the_cached_price() {
+    let mut t = ledger();
+    t.set_price_oracle(Some(user(8))).unwrap();
+    t.record_price(50_000_000);
+    t.redenominate(10, true).unwrap();
+    assert_eq!(t.balance_value_in_icp(owner()), Err("Price is stale or unavailable".to_string()));
+    assert_eq!(t.price_refresh_due(), Some(user(8)));
+}
+
+#[test]
+fn redenominate_that_would_lose_precision_changes_nothing() {
+    let mut t = ledger_with_every_amount();
+    t.fee = 150;
+    let before = every_amount(&t);
+    assert_eq!(t.redenominate(100, false), Err("Redenomination would lose precision".to_string()));
+    assert_eq!(every_amount(&t), before);
+}
+
+#[test]
+fn migrate_decimals_rescales_and_updates_the_metadata() {
+    let mut t = ledger_with_every_amount();
+    let before = every_amount(&t);
+    t.migrate_decimals(10).unwrap();
+    assert_eq!(t.decimals, 10);
+    assert_eq!(every_amount(&t), before.iter().map(|v| v * 100).collect::<Vec<_>>());
+}
+
+#[test]
+fn migrate_decimals_rejects_more_than_38() {
+    let mut t = ledger();
+    t.balances.clear();
+    t.total_supply = 0;
+    assert_eq!(t.migrate_decimals(39), Err("Decimals must be at most 38".to_string()));
+    assert_eq!(t.decimals, 8);
+    assert_eq!(t.migrate_decimals(38), Ok(()));
+}
+
+#[test]
+fn records_in_one_message_share_its_metered_cycles() {
+    let mut t = ledger();
+    env::set_instruction_counter(1_000);
+    t.transfer(owner(), user(1), 10, None).unwrap();
+    env::set_instruction_counter(1_500);
+    t.transfer(owner(), user(2), 10, None).unwrap();
+    // The next message starts its counter over
+    env::set_time(2_000);
+    env::set_instruction_counter(300);
+    t.transfer(owner(), user(3), 10, None).unwrap();
+    let charged: Vec<u64> = t.transaction_history.iter().rev().take(3).map(|r| r.cycles_burnt).collect();
+    assert_eq!(charged, vec![120, 200, 400]);
+    assert_eq!(t.cycles_by_operation(), vec![(TxKind::Transfer, 720)]);
+}
+
+#[test]
+fn a_new_caller_in_the_same_round_is_a_new_message() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 100, None).unwrap();
+    env::set_instruction_counter(1_000);
+    t.transfer(owner(), user(2), 10, None).unwrap();
+    env::set_caller(user(1));
+    env::set_instruction_counter(1_200);
+    t.transfer(user(1), user(2), 10, None).unwrap();
+    assert_eq!(t.transaction_history.last().map(|r| r.cycles_burnt), Some(480));
+}
+
+#[test]
+fn enabling_approvals_reports_an_icrc2_ledger() {
+    let mut t = ledger();
+    t.feature_flags.approve = false;
+    assert_eq!(t.ledger_type(), LedgerType::Icrc1);
+    t.feature_flags.approve = true;
+    assert_eq!(t.ledger_type(), LedgerType::Icrc2);
+}
+
+#[test]
+fn balance_proof_verifies_against_the_published_root() {
+    let mut t = ledger();
+    for n in 1..=4 {
+        t.transfer(owner(), user(n), 10 * n as u128, None).unwrap();
+    }
+    let root = t.balances_merkle_root();
+    let proof = t.balance_proof(user(3)).expect("holder has a proof");
+    assert!(merkle::verify(merkle::leaf_hash(&user(3), 30), &proof, &root));
+    assert!(!merkle::verify(merkle::leaf_hash(&user(3), 31), &proof, &root));
+    assert_eq!(t.balance_proof(user(9)), None);
+}
+
+#[test]
+fn reclaim_takes_only_spendable_and_matured_locked_tokens() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 1_000, None).unwrap();
+    t.set_inactivity_period(Some(MIN_INACTIVITY_PERIOD_NS)).unwrap();
+    t.time_locks.entry(user(1)).or_default().extend([
+        TimeLock { amount: 100, unlock_at: 2_000 },
+        TimeLock { amount: 200, unlock_at: u64::MAX },
+    ]);
+    t.stake(user(1), 300).unwrap();
+    t.hold(user(1), 50).unwrap();
+    assert!(t.propose_reclaim(user(1), owner()).is_err());
+
+    env::set_time(1_000 + MIN_INACTIVITY_PERIOD_NS);
+    t.propose_reclaim(user(1), owner()).unwrap();
+    assert_eq!(t.reclaim_inactive(user(1), owner()), Ok(450));
+    assert_eq!(t.balance_of(user(1)), 550);
+    assert_eq!(t.locked_balance(user(1)), 550);
+    assert_eq!(t.held[&user(1)], 50);
+    assert_eq!(t.stakes[&user(1)].amount, 300);
+    assert_eq!(t.time_locks(user(1)), vec![TimeLock { amount: 200, unlock_at: u64::MAX }]);
+    assert_eq!(t.balance_of(owner()), 1_000_000 - 1_000 + 450);
+}
+
+#[test]
+fn supply_above_u64_max_works_end_to_end() {
+    let big = u64::MAX as u128 * 1_000;
+    let mut t = ledger();
+    t.mint(owner(), big, None).unwrap();
+    assert_eq!(t.total_supply(), 1_000_000 + big);
+    t.transfer(owner(), user(1), big, None).unwrap();
+    t.approve(user(1), user(2), big / 2, None, None).unwrap();
+    t.transfer_from(user(2), user(1), user(3), big / 2, None).unwrap();
+    t.burn(user(3), big / 4).unwrap();
+    assert_eq!(t.balance_of(user(1)), big / 2);
+    assert_eq!(t.balance_of(user(3)), big / 2 - big / 4);
+    assert_eq!(t.total_supply(), 1_000_000 + big - big / 4);
+}
+
+#[test]
+fn mint_past_u128_max_is_refused_without_crediting() {
+    let mut t = ledger();
+    assert_eq!(t.mint(user(1), u128::MAX, None), Err("Supply overflow".to_string()));
+    assert_eq!((t.total_supply(), t.balance_of(user(1))), (1_000_000, 0));
+}
+
+#[test]
+fn reward_claims_that_would_overflow_supply_are_refused() {
+    let mut t = ledger();
+    t.staking_emission = 100_000;
+    t.stake(owner(), 1_000_000).unwrap();
+    env::set_time(1_000 + YEAR_NS);
+    t.total_supply = u128::MAX - 1;
+    assert_eq!(t.claim_rewards(owner()), Err("Supply overflow".to_string()));
+    assert_eq!(t.pending_rewards(owner()), 100_000);
+    t.set_auto_compound(owner(), true).unwrap();
+    assert_eq!(t.compound_stakes(), 0);
+    assert_eq!(t.stakes[&owner()].amount, 1_000_000);
+}
+
+fn staking_ledger(emission: u128) -> TokenICRC2 {
+    let mut t = ledger();
+    t.set_staking_emission(emission).unwrap();
+    t.transfer(owner(), user(1), 300_000, None).unwrap();
+    t
+}
+
+#[test]
+fn doubling_the_total_staked_halves_the_apy() {
+    let mut t = staking_ledger(50_000);
+    assert_eq!(t.current_apy_bps(), 0);
+    t.stake(owner(), 250_000).unwrap();
+    assert_eq!(t.current_apy_bps(), 2_000);
+    t.stake(user(1), 250_000).unwrap();
+    assert_eq!(t.current_apy_bps(), 1_000);
+}
+
+#[test]
+fn emission_is_shared_pro_rata_to_stake() {
+    let mut t = staking_ledger(40_000);
+    t.stake(owner(), 100_000).unwrap();
+    t.stake(user(1), 300_000).unwrap();
+    env::set_time(1_000 + YEAR_NS / 2);
+    assert_eq!((t.pending_rewards(owner()), t.pending_rewards(user(1))), (5_000, 15_000));
+    // The owner now holds half the total, so the next half year splits evenly
+    t.unstake(user(1), 200_000).unwrap();
+    env::set_time(1_000 + YEAR_NS);
+    assert_eq!((t.pending_rewards(owner()), t.pending_rewards(user(1))), (15_000, 25_000));
+    assert_eq!(t.claim_rewards(user(1)), Ok(25_000));
+    assert_eq!(t.pending_rewards(owner()), 15_000);
+}
+
+#[test]
+fn pausing_mint_blocks_minting_while_transfers_continue() {
+    let mut t = ledger();
+    t.pause_operation(TxKind::Mint).unwrap();
+    assert_eq!(t.mint(user(1), 10, None), Err("Operation paused".to_string()));
+    assert!(t.feature_flags().mint);
+    assert_eq!(t.paused_operations(), vec![TxKind::Mint]);
+    t.transfer(owner(), user(1), 10, None).unwrap();
+    t.unpause_operation(TxKind::Mint).unwrap();
+    t.mint(user(1), 10, None).unwrap();
+    assert_eq!(t.balance_of(user(1)), 20);
+}
+
+#[test]
+fn a_paused_transfer_is_rejected_in_reject_mode() {
+    let mut t = ledger();
+    t.pause_operation(TxKind::Transfer).unwrap();
+    assert_eq!(t.transfer(owner(), user(1), 10, None), Err("Operation paused".to_string()));
+    assert!(t.queued_transfers.is_empty());
+    assert_eq!(t.pause_operation(TxKind::Reap), Err("Operation cannot be paused".to_string()));
+}
+
+#[test]
+fn transfers_queued_while_paused_run_in_order_on_unpause() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 100, None).unwrap();
+    t.set_pause_mode(PauseMode::Queue).unwrap();
+    t.pause_operation(TxKind::Transfer).unwrap();
+    t.transfer(owner(), user(2), 10, None).unwrap();
+    t.transfer(user(1), user(3), 80, None).unwrap();
+    t.transfer(owner(), user(3), 20, None).unwrap();
+    assert_eq!((t.balance_of(user(2)), t.balance_of(user(3))), (0, 0));
+    assert_eq!(t.queued_transfers().len(), 3);
+    // The queued 80 no longer fits once user 1 has burned half its balance
+    t.burn(user(1), 50).unwrap();
+
+    t.unpause_operation(TxKind::Transfer).unwrap();
+    assert!(t.queued_transfers.is_empty());
+    assert_eq!((t.balance_of(user(1)), t.balance_of(user(2)), t.balance_of(user(3))), (50, 10, 20));
+    let moved: Vec<(Principal, u128)> = t.recent_transactions(2).iter().map(|r| (r.to, r.amount)).collect();
+    assert_eq!(moved, vec![(user(3), 20), (user(2), 10)]);
+    let failed: Vec<(String, Principal)> =
+        t.get_failed_operations(0, 10).into_iter().map(|f| (f.operation, f.caller)).collect();
+    assert_eq!(failed, vec![("queued_transfer".to_string(), user(1))]);
+}
+
+#[test]
+fn a_disabled_transfer_flag_rejects_even_in_queue_mode() {
+    let mut t = ledger();
+    t.set_pause_mode(PauseMode::Queue).unwrap();
+    t.feature_flags.transfer = false;
+    assert_eq!(t.transfer(owner(), user(1), 10, None), Err("Operation disabled".to_string()));
+    assert!(t.queued_transfers.is_empty());
+    assert_eq!(t.balance_of(user(1)), 0);
+}
+
+#[test]
+fn v5_queue_mode_with_transfers_off_restores_as_a_pause() {
+    let mut t = ledger();
+    t.pause_mode = PauseMode::Queue;
+    t.feature_flags.transfer = false;
+    t.queued_transfers.push_back(QueuedTransfer { from: owner(), to: user(3), amount: 40, memo: None, queued_at: 5 });
+    let mut restored = restore_state(&v5_snapshot_without(&t, &["paused_operations"])).expect("v5 state was dropped");
+    assert!(restored.feature_flags.transfer);
+    assert_eq!(restored.paused_operations(), vec![TxKind::Transfer]);
+    restored.unpause_operation(TxKind::Transfer).unwrap();
+    assert_eq!(restored.balance_of(user(3)), 40);
+}
+
+#[test]
+fn distribution_reaches_holders_the_registry_has_not_confirmed() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 500_000, None).unwrap();
+    t.user_registry = Some(user(9));
+    assert_eq!(t.distribute_pro_rata(3_000, None), Ok(2));
+    assert_eq!((t.balance_of(owner()), t.balance_of(user(1))), (501_500, 501_500));
+}
+
+#[test]
+fn parse_amount_reads_what_format_amount_writes() {
+    let t = ledger();
+    assert_eq!(t.parse_amount("1.5"), Ok(150_000_000));
+    assert_eq!(t.parse_amount(" 0.00000001 "), Ok(1));
+    assert_eq!(t.parse_amount("42"), Ok(4_200_000_000));
+    let amount = 123_456_789_012;
+    assert_eq!(t.parse_amount(&format_amount(amount, 8)), Ok(amount));
+}
+
+#[test]
+fn parse_amount_rejects_malformed_input() {
+    let t = ledger();
+    for input in ["", ".5", "1.", "1.2.3", "-1", "+1", "1e5", "1,5", "0x10", "١"] {
+        assert_eq!(t.parse_amount(input), Err("Invalid amount".to_string()), "{:?}", input);
+    }
+}
+
+#[test]
+fn parse_amount_rejects_excess_precision_and_overflow() {
+    let t = ledger();
+    assert_eq!(t.parse_amount("0.000000001"), Err("Too many decimal places".to_string()));
+    let max = format_amount(u128::MAX, 8);
+    assert_eq!(t.parse_amount(&max), Ok(u128::MAX));
+    let past_max = format_amount(u128::MAX / 10 + 1, 7);
+    assert_eq!(t.parse_amount(&past_max), Err("Amount too large".to_string()));
+    assert_eq!(t.parse_amount(&"9".repeat(40)), Err("Amount too large".to_string()));
+}
+
+fn two_of_three_minters() -> TokenICRC2 {
+    let mut t = ledger();
+    t.add_minter(user(1)).unwrap();
+    t.add_minter(user(2)).unwrap();
+    t.set_mint_threshold(2).unwrap();
+    t
+}
+
+#[test]
+fn a_two_of_three_mint_runs_on_the_second_approval() {
+    let mut t = two_of_three_minters();
+    let id = t.propose_mint(user(5), 500, None).unwrap();
+    assert_eq!(t.balance_of(user(5)), 0);
+    assert_eq!(t.approve_mint(id), Err("Caller has already approved this proposal".to_string()));
+    env::set_caller(user(1));
+    assert_eq!(t.approve_mint(id), Ok(true));
+    assert_eq!((t.balance_of(user(5)), t.total_supply()), (500, 1_000_500));
+    assert!(t.get_mint_proposal(id).is_none());
+    env::set_caller(user(2));
+    assert_eq!(t.approve_mint(id), Err("Unknown mint proposal".to_string()));
+}
+
+#[test]
+fn a_proposal_whose_mint_fails_keeps_its_approvals() {
+    let mut t = two_of_three_minters();
+    let id = t.propose_mint(user(5), 500, None).unwrap();
+    t.pause_operation(TxKind::Mint).unwrap();
+    env::set_caller(user(1));
+    assert_eq!(t.approve_mint(id), Err("Operation paused".to_string()));
+    assert_eq!(t.get_mint_proposal(id).map(|p| p.approvals), Some(vec![owner()]));
+    env::set_caller(owner());
+    t.unpause_operation(TxKind::Mint).unwrap();
+    env::set_caller(user(1));
+    assert_eq!(t.approve_mint(id), Ok(true));
+    assert_eq!(t.balance_of(user(5)), 500);
+}
+
+#[test]
+fn a_single_approval_mint_that_fails_leaves_no_proposal() {
+    let mut t = ledger();
+    t.set_require_custody_ref(true).unwrap();
+    assert_eq!(t.propose_mint(user(5), 500, None), Err("Custody reference required".to_string()));
+    t.pause_operation(TxKind::Mint).unwrap();
+    assert_eq!(t.propose_mint(user(5), 500, Some("ref".to_string())), Err("Operation paused".to_string()));
+    assert!(t.mint_proposals.is_empty());
+}
+
+#[test]
+fn a_rejected_notification_rolls_the_transfer_back_less_the_fee() {
+    let mut t = ledger();
+    t.set_fee(10).unwrap();
+    t.set_fee_collector(user(9)).unwrap();
+    let received = t.transfer_and_hold(owner(), user(7), 100).unwrap();
+    t.rollback_transfer(owner(), user(7), received).unwrap();
+    assert_eq!(t.balance_of(owner()), 1_000_000 - 10);
+    assert_eq!((t.balance_of(user(7)), t.balance_of(user(9))), (0, 10));
+    assert!(t.held.is_empty());
+    let rollback = t.transaction_history.last().unwrap();
+    assert_eq!((rollback.from, rollback.to, rollback.amount, rollback.fee), (user(7), owner(), 100, 0));
+}
+
+#[test]
+fn a_fresh_cached_price_sets_the_fiat_fee() {
+    let mut t = ledger();
+    t.set_fee(7).unwrap();
+    t.set_fiat_fee(Some(5)).unwrap();
+    t.set_price_oracle(Some(user(8))).unwrap();
+    // No price yet: the flat fee applies and a refresh is due
+    assert_eq!(t.effective_fee(), 7);
+    assert_eq!(t.price_refresh_due(), Some(user(8)));
+    t.record_price(50_000_000);
+    assert_eq!(t.price_refresh_due(), None);
+    assert_eq!(t.effective_fee(), 10);
+    t.set_fee_collector(user(9)).unwrap();
+    t.transfer(owner(), user(1), 100, None).unwrap();
+    assert_eq!((t.balance_of(owner()), t.balance_of(user(9))), (1_000_000 - 110, 10));
+}
+
+#[test]
+fn a_stale_cached_price_falls_back_to_the_flat_fee() {
+    let mut t = ledger();
+    t.set_fee(7).unwrap();
+    t.set_fiat_fee(Some(5)).unwrap();
+    t.set_price_oracle(Some(user(8))).unwrap();
+    t.record_price(50_000_000);
+    env::set_time(1_000 + t.price_ttl_ns);
+    assert_eq!(t.effective_fee(), 7);
+    assert_eq!(t.price_refresh_due(), Some(user(8)));
+}
+
+#[test]
+fn volume_counts_transfers_inside_the_window_once() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 100, None).unwrap();
+    env::set_time(2_000);
+    t.transfer(owner(), user(1), 20, None).unwrap();
+    t.approve(owner(), user(2), 50, None, None).unwrap();
+    env::set_time(2_500);
+    t.transfer_from(user(2), owner(), user(3), 30, None).unwrap();
+    t.burn(user(1), 10).unwrap();
+    env::set_time(2_600);
+    let received = t.transfer_and_hold(owner(), user(7), 40).unwrap();
+    t.rollback_transfer(owner(), user(7), received).unwrap();
+    env::set_time(3_000);
+    t.transfer(owner(), user(1), 5, None).unwrap();
+    assert_eq!(t.transaction_history.iter().filter(|r| r.kind == TxKind::Rollback).count(), 1);
+    assert_eq!(t.volume_in_window(2_000, 3_000), 90);
+    assert_eq!(t.volume_in_window(0, u64::MAX), 195);
+    assert_eq!(t.volume_in_window(2_601, 3_000), 0);
+}
+
+fn run_batch(t: &mut TokenICRC2, from: Principal, transfers: &[(Principal, u128)]) -> BatchResult {
+    let mut batch = BatchResult::default();
+    for (index, (to, amount)) in transfers.iter().enumerate() {
+        let outcome = t.batch_transfer_entry(from, *to, *amount, None);
+        batch.push(index as u64, *amount, outcome);
+    }
+    batch
+}
+
+#[test]
+fn batch_totals_count_what_each_entry_moved() {
+    let mut t = ledger();
+    t.set_fee(10).unwrap();
+    t.transfer(owner(), user(1), 500, None).unwrap();
+    let batch = run_batch(&mut t, user(1), &[(user(2), 100), (user(3), 1_000), (user(4), 200)]);
+    assert_eq!(batch.succeeded, vec![(0, 100), (2, 200)]);
+    assert_eq!(batch.failed, vec![(1, "Insufficient balance".to_string())]);
+    assert_eq!((batch.total_transferred, batch.total_fees), (300, 20));
+
+    // The recipient pays: the sender is debited the amount, recipients get it less the fee
+    t.set_fee_payer(FeePayer::Recipient).unwrap();
+    let batch = run_batch(&mut t, owner(), &[(user(2), 100), (user(3), 5)]);
+    assert_eq!(batch.succeeded, vec![(0, 100)]);
+    assert_eq!((batch.total_transferred, batch.total_fees), (90, 10));
+}
+
+#[test]
+fn queued_batch_entries_add_nothing_to_the_totals() {
+    let mut t = ledger();
+    t.set_fee(10).unwrap();
+    t.set_pause_mode(PauseMode::Queue).unwrap();
+    t.pause_operation(TxKind::Transfer).unwrap();
+    let batch = run_batch(&mut t, owner(), &[(user(2), 100), (user(3), 200)]);
+    assert_eq!(batch.succeeded, vec![(0, 100), (1, 200)]);
+    assert_eq!((batch.total_transferred, batch.total_fees), (0, 0));
+    assert_eq!(t.queued_transfers().len(), 2);
+}
+
+#[test]
+fn supply_drift_reports_the_sign_and_size_of_a_corrupted_balance() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 1_000, None).unwrap();
+    t.burn(user(1), 300).unwrap();
+    assert_eq!((t.supply_drift(), t.burned_total()), (0, 300));
+    assert!(t.verify_supply_invariant());
+    // A balance credited out of nowhere
+    *t.balances.get_mut(&user(1)).unwrap() += 25;
+    assert_eq!(t.supply_drift(), -25);
+    // Supply that no balance holds
+    *t.balances.get_mut(&owner()).unwrap() -= 100;
+    assert_eq!(t.supply_drift(), 75);
+    assert!(!t.verify_supply_invariant());
+}
+
+#[test]
+fn locked_tokens_reduce_spendable_but_not_total() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 1_000, None).unwrap();
+    t.hold(user(1), 100).unwrap();
+    t.stake(user(1), 200).unwrap();
+    t.time_locks.entry(user(1)).or_default().push(TimeLock { amount: 300, unlock_at: 5_000 });
+    assert_eq!((t.balance_of(user(1)), t.spendable_balance(user(1))), (1_000, 400));
+    assert_eq!(t.transfer(user(1), user(2), 401, None), Err("Insufficient balance".to_string()));
+    t.transfer(user(1), user(2), 400, None).unwrap();
+    assert_eq!((t.balance_of(user(1)), t.spendable_balance(user(1))), (600, 0));
+    // The time lock matures; holds and stakes stay
+    env::set_time(5_000);
+    assert_eq!(t.spendable_balance(user(1)), 300);
+}
+
+#[test]
+fn approving_above_the_spender_ceiling_is_rejected() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 1_000, None).unwrap();
+    t.set_max_allowance(Some(500)).unwrap();
+    assert_eq!(t.approve(owner(), user(5), 501, None, None), Err("Allowance exceeds spender ceiling".to_string()));
+    t.approve(owner(), user(5), 300, None, None).unwrap();
+    // The ceiling is per spender across owners
+    assert_eq!(t.approve(user(1), user(5), 201, None, None), Err("Allowance exceeds spender ceiling".to_string()));
+    t.approve(user(1), user(5), 200, None, None).unwrap();
+    // Replacing an approval only counts the new amount
+    t.approve(owner(), user(5), 250, None, None).unwrap();
+    assert_eq!(t.total_allowance_for_spender(user(5)), 450);
+}
+
+#[test]
+fn a_locked_account_can_transfer_after_the_unlock_time() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 100, None).unwrap();
+    t.lock_account_until(user(1), 5_000).unwrap();
+    assert_eq!(t.transfer(user(1), user(2), 10, None), Err("Account is locked".to_string()));
+    // Receiving is unaffected
+    t.transfer(owner(), user(1), 10, None).unwrap();
+    env::set_time(4_999);
+    assert_eq!(t.account_locked_until(user(1)), Some(5_000));
+    assert!(t.transfer(user(1), user(2), 10, None).is_err());
+    env::set_time(5_000);
+    assert_eq!(t.account_locked_until(user(1)), None);
+    t.transfer(user(1), user(2), 10, None).unwrap();
+    assert_eq!(t.balance_of(user(2)), 10);
+    assert!(!t.locked_until.contains_key(&user(1)));
+}
+
+#[test]
+fn reaping_starts_just_below_the_existential_deposit() {
+    let mut t = ledger();
+    t.set_fee_collector(user(9)).unwrap();
+    t.set_existential_deposit(50).unwrap();
+    t.transfer(owner(), user(1), 200, None).unwrap();
+    t.transfer(owner(), user(2), 200, None).unwrap();
+    // Exactly at the threshold the account stays
+    t.transfer(user(1), user(3), 150, None).unwrap();
+    assert_eq!(t.balance_of(user(1)), 50);
+    assert!(t.balances.contains_key(&user(1)));
+    // One unit below it the remainder goes to the fee collector
+    t.transfer(user(2), user(3), 151, None).unwrap();
+    assert_eq!((t.balance_of(user(2)), t.balance_of(user(9))), (0, 49));
+    assert!(!t.balances.contains_key(&user(2)));
+    let reap = t.transaction_history.last().unwrap();
+    assert_eq!((reap.kind, reap.from, reap.to, reap.amount), (TxKind::Reap, user(2), user(9), 49));
+    assert_eq!(t.supply_drift(), 0);
+}
+
+#[test]
+fn legacy_and_icrc1_balance_queries_agree_for_the_default_subaccount() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 123, None).unwrap();
+    TOKEN_ICRC2.with(|token| *token.borrow_mut() = Some(t));
+    let default = Account { owner: user(1), subaccount: None };
+    assert_eq!(balance_of(user(1)), 123);
+    assert_eq!(icrc1_balance_of(default), balance_of(user(1)));
+    assert_eq!(icrc1_balance_of(Account { subaccount: Some([0; 32]), ..default }), 123);
+    assert_eq!(icrc1_balance_of(Account { subaccount: Some([1; 32]), ..default }), 0);
+}
+
+#[test]
+fn burns_are_addressed_to_the_burn_account_and_counted() {
+    let mut t = ledger();
+    assert_eq!(t.burn_account(), Principal::management_canister());
+    t.set_burn_account(user(6)).unwrap();
+    t.burn(owner(), 300).unwrap();
+    t.burn(owner(), 200).unwrap();
+    let burn = t.transaction_history.last().unwrap();
+    assert_eq!((burn.kind, burn.to), (TxKind::Burn, user(6)));
+    // The tokens are destroyed, not parked at the sink
+    assert_eq!(t.balance_of(user(6)), 0);
+    assert_eq!((t.burned_total(), t.total_supply()), (500, 999_500));
+}
+
+#[test]
+fn transfers_to_the_ledger_itself_are_rejected_by_default() {
+    let mut t = ledger();
+    let ledger_id = env::canister_id();
+    assert_eq!(t.transfer(owner(), ledger_id, 10, None), Err("Cannot transfer to ledger; use deposit".to_string()));
+    assert_eq!(t.balance_of(ledger_id), 0);
+    t.set_accept_direct_deposits(true).unwrap();
+    t.transfer(owner(), ledger_id, 10, None).unwrap();
+    assert_eq!(t.balance_of(ledger_id), 10);
+}
+
+#[test]
+fn recent_transactions_are_newest_first_and_capped() {
+    let mut t = ledger();
+    for n in 1..=120u128 {
+        t.transfer(owner(), user(1), n, None).unwrap();
+    }
+    let amounts: Vec<u128> = t.recent_transactions(3).iter().map(|r| r.amount).collect();
+    assert_eq!(amounts, vec![120, 119, 118]);
+    assert_eq!(t.recent_transactions(1_000).len(), 100);
+    assert_eq!(t.recent_transactions(1_000).last().map(|r| r.amount), Some(21));
+    assert!(t.recent_transactions(0).is_empty());
+}
+
+#[test]
+fn a_failed_transfer_is_logged_and_counted() {
+    let mut t = ledger();
+    let result = t.transfer(user(1), user(2), 10, None);
+    assert!(t.log_failure("transfer", user(1), result).is_err());
+    let result = t.transfer(user(1), user(2), 20, None);
+    let _ = t.log_failure("transfer", user(1), result);
+    let result = t.transfer(owner(), user(2), 10, None);
+    assert_eq!(t.log_failure("transfer", owner(), result), Ok(()));
+    let failed = t.get_failed_operations(0, 10);
+    assert_eq!(failed.len(), 2);
+    assert_eq!((failed[0].operation.as_str(), failed[0].caller), ("transfer", user(1)));
+    assert_eq!((failed[0].error.as_str(), failed[0].timestamp), ("Insufficient balance", 1_000));
+    assert_eq!(t.error_counts(), vec![("Insufficient balance".to_string(), 2)]);
+}
+
+#[test]
+fn allowance_total_sums_every_owner_approving_the_spender() {
+    let mut t = ledger();
+    t.approve(owner(), user(5), 300, None, None).unwrap();
+    t.approve(user(1), user(5), 200, None, None).unwrap();
+    t.approve(user(1), user(6), 999, None, None).unwrap();
+    assert_eq!(t.total_allowance_for_spender(user(5)), 500);
+    t.approve(user(2), user(5), 50, Some(2_000), None).unwrap();
+    assert_eq!(t.total_allowance_for_spender(user(5)), 550);
+    env::set_time(2_000);
+    assert_eq!(t.total_allowance_for_spender(user(5)), 500);
+}
+
+#[test]
+fn memo_less_transfers_fail_when_a_memo_is_required() {
+    let mut t = ledger();
+    t.approve(owner(), user(2), 100, None, None).unwrap();
+    t.set_require_memo(true).unwrap();
+    assert_eq!(t.transfer(owner(), user(1), 10, None), Err("Memo required".to_string()));
+    assert_eq!(t.transfer(owner(), user(1), 10, Some(Vec::new())), Err("Memo required".to_string()));
+    assert_eq!(t.transfer_from(user(2), owner(), user(1), 10, None), Err("Memo required".to_string()));
+    t.transfer(owner(), user(1), 10, Some(b"user-42".to_vec())).unwrap();
+    t.transfer_from(user(2), owner(), user(1), 10, Some(b"user-43".to_vec())).unwrap();
+    assert_eq!(t.balance_of(user(1)), 20);
+}
+
+#[test]
+fn transfers_above_max_tx_amount_are_rejected_unless_exempt() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 1_000, None).unwrap();
+    t.transfer(owner(), user(2), 1_000, None).unwrap();
+    t.set_max_tx_amount(Some(100)).unwrap();
+    assert_eq!(t.transfer(user(1), user(3), 101, None), Err("Exceeds max transaction size".to_string()));
+    t.transfer(user(1), user(3), 100, None).unwrap();
+    t.transfer(owner(), user(3), 500, None).unwrap();
+    t.set_exempt(user(2), true).unwrap();
+    t.transfer(user(2), user(3), 500, None).unwrap();
+    assert_eq!(t.balance_of(user(3)), 1_100);
+}
+
+#[test]
+fn max_wallet_caps_non_exempt_recipients_only() {
+    let mut t = ledger();
+    t.set_max_wallet(Some(500)).unwrap();
+    t.transfer(owner(), user(1), 400, None).unwrap();
+    assert_eq!(t.transfer(owner(), user(1), 101, None), Err("Exceeds max wallet size".to_string()));
+    assert_eq!(t.mint(user(1), 101, None), Err("Exceeds max wallet size".to_string()));
+    t.transfer(owner(), user(1), 100, None).unwrap();
+    assert_eq!(t.balance_of(user(1)), 500);
+    t.set_exempt(user(2), true).unwrap();
+    t.transfer(owner(), user(2), 1_000, None).unwrap();
+    t.mint(user(2), 1_000, None).unwrap();
+    assert_eq!(t.balance_of(user(2)), 2_000);
+}
+
+#[test]
+fn the_tip_moves_to_each_new_transaction() {
+    let mut t = ledger();
+    assert_eq!(t.tip(), None);
+    t.transfer(owner(), user(1), 10, None).unwrap();
+    let (index, hash) = t.tip().expect("history has a tip");
+    // Same content at the same time: the chain still moves on
+    t.transfer(owner(), user(1), 10, None).unwrap();
+    let (next_index, next_hash) = t.tip().unwrap();
+    assert_eq!(next_index, index + 1);
+    assert_ne!(next_hash, hash);
+    assert_eq!(t.transaction_hash(index), t.transaction_hash(next_index));
+}
+
+#[test]
+fn disabling_transfer_from_leaves_transfer_working() {
+    let mut t = ledger();
+    t.approve(owner(), user(2), 100, None, None).unwrap();
+    let flags = FeatureFlags { transfer_from: false, ..t.feature_flags() };
+    t.set_feature_flags(flags).unwrap();
+    assert_eq!(t.transfer_from(user(2), owner(), user(1), 10, None), Err("Operation disabled".to_string()));
+    t.transfer(owner(), user(1), 10, None).unwrap();
+    assert_eq!((t.balance_of(user(1)), t.allowance(owner(), user(2))), (10, 100));
+    let standards: Vec<String> = t.icrc1_supported_standards().into_iter().map(|s| s.name).collect();
+    assert_eq!(standards, vec!["ICRC-1".to_string()]);
+}
+
+#[test]
+fn transfer_all_leaves_the_sender_at_exactly_zero() {
+    let mut t = ledger();
+    t.set_fee(10).unwrap();
+    t.set_fee_collector(user(9)).unwrap();
+    t.transfer(owner(), user(1), 1_000, None).unwrap();
+    assert_eq!(t.transfer_all(user(1), user(2), None), Ok(990));
+    assert_eq!((t.balance_of(user(1)), t.balance_of(user(2))), (0, 990));
+    // Locked tokens stay behind; only the spendable part is sent
+    t.time_locks.entry(user(2)).or_default().push(TimeLock { amount: 400, unlock_at: u64::MAX });
+    assert_eq!(t.transfer_all(user(2), user(3), None), Ok(580));
+    assert_eq!((t.balance_of(user(2)), t.spendable_balance(user(2))), (400, 0));
+    t.transfer(owner(), user(4), 10, None).unwrap();
+    assert_eq!(t.transfer_all(user(4), user(3), None), Err("Balance does not cover the fee".to_string()));
+    assert_eq!(t.balance_of(user(4)), 10);
+}
+
+#[test]
+fn expiring_allowances_lists_only_those_due_within_the_window() {
+    let mut t = ledger();
+    t.approve(owner(), user(1), 10, Some(1_500), None).unwrap();
+    t.approve(owner(), user(2), 10, Some(3_000), None).unwrap();
+    t.approve(owner(), user(3), 10, Some(2_000), None).unwrap();
+    t.approve(owner(), user(4), 10, Some(9_000), None).unwrap();
+    t.approve(owner(), user(5), 10, None, None).unwrap();
+    env::set_time(1_600);
+    assert_eq!(
+        t.expiring_allowances(1_400),
+        vec![(owner(), user(3), 2_000), (owner(), user(2), 3_000)]
+    );
+    assert!(t.expiring_allowances(0).is_empty());
+}
+
+#[test]
+fn mint_locked_tokens_are_held_but_not_spendable_until_unlock() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 50, None).unwrap();
+    t.mint_locked(user(1), 1_000, 5_000, None).unwrap();
+    assert_eq!((t.balance_of(user(1)), t.spendable_balance(user(1))), (1_050, 50));
+    assert_eq!(t.total_supply(), 1_001_000);
+    assert_eq!(t.transfer(user(1), user(2), 100, None), Err("Insufficient balance".to_string()));
+    assert_eq!(t.mint_locked(user(1), 10, 1_000, None), Err("Unlock time must be in the future".to_string()));
+    env::set_time(5_000);
+    assert_eq!(t.spendable_balance(user(1)), 1_050);
+    t.transfer(user(1), user(2), 1_050, None).unwrap();
+}
+
+#[test]
+fn total_supply_display_renders_the_supply_exactly() {
+    let t = TokenICRC2::new(owner(), 1_000_000, 6, "Token".to_string(), "TKN".to_string());
+    assert_eq!(t.total_supply_display(), "1.000000");
+    assert_eq!(format_amount(42, 6), "0.000042");
+    assert_eq!(format_amount(1_000_000, 0), "1000000");
+    assert_eq!(format_amount(u128::MAX, 8), "3402823669209384634633746074317.68211455");
+}
+
+#[test]
+fn a_denying_or_failing_authorizer_blocks_the_spend() {
+    use ic_cdk::api::call::RejectionCode;
+    let denied = Err("Spend not authorized".to_string());
+    assert_eq!(spend_decision(Ok((false,))), denied);
+    assert_eq!(spend_decision(Err((RejectionCode::CanisterReject, "no".to_string()))), denied);
+    assert_eq!(spend_decision(Err((RejectionCode::CanisterError, "trapped".to_string()))), denied);
+    assert_eq!(spend_decision(Ok((true,))), Ok(()));
+}
+
+#[test]
+fn account_tx_count_matches_the_transfers_involving_the_account() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 100, None).unwrap();
+    t.transfer(user(1), user(2), 10, None).unwrap();
+    t.transfer(owner(), user(2), 10, None).unwrap();
+    t.transfer(user(2), user(1), 5, None).unwrap();
+    t.transfer(user(1), user(1), 5, None).unwrap();
+    assert_eq!(t.account_tx_count(user(1)), 4);
+    assert_eq!(t.account_tx_count(user(2)), 3);
+    assert_eq!(t.account_tx_count(user(3)), 0);
+}
+
+fn genesis_ledger() -> TokenICRC2 {
+    env::set_caller(owner());
+    TokenICRC2::new(owner(), 1_000, 8, "Token".to_string(), "TKN".to_string())
+}
+
+#[test]
+fn genesis_allocations_are_credited_with_the_remainder_to_the_owner() {
+    let mut t = genesis_ledger();
+    t.allocate_genesis(vec![(user(1), 600), (user(2), 300)]).unwrap();
+    assert_eq!((t.balance_of(user(1)), t.balance_of(user(2)), t.balance_of(owner())), (600, 300, 100));
+    assert_eq!(t.total_supply(), 1_000);
+    assert_eq!(t.supply_drift(), 0);
+    let genesis: Vec<(Principal, u128)> = t.transaction_history.iter().map(|r| (r.to, r.amount)).collect();
+    assert_eq!(genesis, vec![(user(1), 600), (user(2), 300)]);
+}
+
+#[test]
+fn overflowing_oversized_or_duplicate_allocations_are_rejected() {
+    let mut t = genesis_ledger();
+    assert_eq!(t.allocate_genesis(vec![(user(1), u128::MAX), (user(2), 1)]), Err("Allocations overflow".to_string()));
+    assert_eq!(t.allocate_genesis(vec![(user(1), 600), (user(2), 401)]), Err("Allocations exceed total supply".to_string()));
+    assert_eq!(t.allocate_genesis(vec![(user(1), 100), (user(1), 100)]), Err("Duplicate allocation".to_string()));
+    assert_eq!(t.balance_of(owner()), 1_000);
+    assert!(t.transaction_history.is_empty());
+}
+
+#[test]
+fn operations_past_the_rate_limit_are_rejected_until_the_window_rolls() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 100, None).unwrap();
+    t.set_rate_limit(Some(RateLimit { max_ops: 3, window_ns: 1_000 })).unwrap();
+    t.transfer(user(1), user(2), 1, None).unwrap();
+    t.approve(user(1), user(2), 10, None, None).unwrap();
+    env::set_time(1_500);
+    t.burn(user(1), 1).unwrap();
+    assert_eq!(t.transfer(user(1), user(2), 1, None), Err("Rate limit exceeded".to_string()));
+    assert_eq!(t.approve(user(1), user(3), 10, None, None), Err("Rate limit exceeded".to_string()));
+    // Another principal has its own window
+    t.transfer(owner(), user(2), 1, None).unwrap();
+    // The first two attempts fall out of the window at 2_000
+    env::set_time(2_000);
+    t.transfer(user(1), user(2), 1, None).unwrap();
+    assert_eq!(t.balance_of(user(2)), 3);
+}
+
+#[test]
+fn approve_debits_the_approve_fee() {
+    let mut t = ledger();
+    t.set_fee_collector(user(9)).unwrap();
+    t.set_approve_fee(25).unwrap();
+    t.approve(owner(), user(2), 100, None, Some(25)).unwrap();
+    assert_eq!((t.balance_of(owner()), t.balance_of(user(9))), (1_000_000 - 25, 25));
+    assert_eq!(t.transaction_history.last().map(|r| (r.kind, r.fee)), Some((TxKind::Approve, 25)));
+    assert_eq!(t.approve(owner(), user(2), 100, None, Some(10)), Err("Bad fee: expected 25".to_string()));
+    assert_eq!(t.approve(user(1), user(2), 100, None, None), Err("Insufficient balance for approve fee".to_string()));
+    assert_eq!(t.balance_of(owner()), 1_000_000 - 25);
+}
+
+#[test]
+fn dashboard_assembles_balance_allowances_and_metadata() {
+    let mut t = ledger();
+    t.set_fee(5).unwrap();
+    t.transfer(owner(), user(1), 1_000, None).unwrap();
+    t.approve(user(1), user(3), 70, None, None).unwrap();
+    t.approve(user(1), user(2), 40, None, None).unwrap();
+    t.hold(user(1), 100).unwrap();
+    let d = t.dashboard(user(1), vec![user(2), user(4), user(3)]);
+    assert_eq!((d.balance, d.spendable, d.fee), (1_000, 900, 5));
+    assert_eq!(d.allowances, vec![(user(2), 40), (user(4), 0), (user(3), 70)]);
+    assert_eq!((d.token.symbol.as_str(), d.token.name.as_str()), ("TKN", "Token"));
+    assert_eq!((d.token.decimals, d.token.total_supply, d.token.owner), (8, 1_000_000, owner()));
+}
+
+#[test]
+fn transfer_from_records_who_acted_and_whose_funds_moved() {
+    let mut t = ledger();
+    t.approve(owner(), user(2), 100, None, None).unwrap();
+    env::set_caller(user(2));
+    t.transfer_from(user(2), owner(), user(3), 60, None).unwrap();
+    let r = t.transaction_history.last().unwrap();
+    assert_eq!((r.kind, r.from, r.to, r.amount), (TxKind::TransferFrom, owner(), user(3), 60));
+    assert_eq!((r.caller, r.authorized_by), (Some(user(2)), Some(owner())));
+}
+
+#[test]
+fn reap_expired_clears_every_category_and_frees_locked_funds() {
+    let mut t = ledger();
+    t.approve(owner(), user(2), 100, Some(2_000), None).unwrap();
+    t.mint_locked(user(1), 500, 3_000, None).unwrap();
+    t.lock_account_until(user(3), 2_500).unwrap();
+    t.add_minter(user(4)).unwrap();
+    t.set_mint_threshold(2).unwrap();
+    t.mint_proposal_ttl_ns = 1_000;
+    let id = t.propose_mint(user(5), 10, None).unwrap();
+
+    env::set_time(3_000);
+    let summary = t.reap_expired();
+    assert_eq!(
+        (summary.allowances, summary.time_locks, summary.account_locks, summary.mint_proposals),
+        (1, 1, 1, 1)
+    );
+    assert!(t.allowances.is_empty() && t.allowance_expiries.is_empty());
+    assert!(t.time_locks.is_empty() && t.locked_until.is_empty());
+    assert!(t.get_mint_proposal(id).is_none());
+    assert_eq!(t.spendable_balance(user(1)), 500);
+    assert_eq!(t.reap_expired(), ReapSummary::default());
+}
+
+#[test]
+fn the_maintenance_callback_sweeps_the_installed_ledger() {
+    let mut t = ledger();
+    t.approve(owner(), user(2), 100, Some(2_000), None).unwrap();
+    t.mint_locked(user(1), 500, 2_000, None).unwrap();
+    TOKEN_ICRC2.with(|token| *token.borrow_mut() = Some(t));
+
+    env::set_time(2_000);
+    run_maintenance();
+    TOKEN_ICRC2.with(|token| {
+        let token = token.borrow();
+        let t = token.as_ref().unwrap();
+        assert!(t.allowance_expiries.is_empty() && t.time_locks.is_empty());
+        assert_eq!(t.spendable_balance(user(1)), 500);
+    });
+}
+
+#[test]
+fn account_summary_reflects_locks_holds_and_live_approvals() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 1_000, None).unwrap();
+    t.mint_locked(user(1), 300, 5_000, None).unwrap();
+    t.hold(user(1), 200).unwrap();
+    t.approve(user(1), user(2), 150, None, None).unwrap();
+    t.approve(user(1), user(3), 400, Some(2_000), None).unwrap();
+    env::set_time(2_000);
+
+    // The funding transfer, the locked mint and both approvals
+    assert_eq!(
+        t.account_summary(user(1)),
+        AccountSummary {
+            balance: 1_300,
+            spendable: 800,
+            locked: 300,
+            held: 200,
+            outgoing_allowances_total: 150,
+            tx_count: 4,
+        }
+    );
+}
+
+#[test]
+fn transfer_from_charges_its_own_fee_and_transfer_the_base_fee() {
+    let mut t = ledger();
+    t.set_fee_collector(user(9)).unwrap();
+    t.set_fee(5).unwrap();
+    t.set_transfer_from_fee(Some(20)).unwrap();
+    t.transfer(owner(), user(1), 1_000, None).unwrap();
+    assert_eq!((t.balance_of(user(1)), t.balance_of(user(9))), (1_000, 5));
+
+    t.approve(user(1), user(2), 500, None, None).unwrap();
+    env::set_caller(user(2));
+    t.transfer_from(user(2), user(1), user(3), 100, None).unwrap();
+    assert_eq!((t.balance_of(user(1)), t.balance_of(user(3)), t.balance_of(user(9))), (880, 100, 25));
+
+    env::set_caller(owner());
+    t.set_transfer_from_fee(None).unwrap();
+    assert_eq!(t.transfer_from_fee(), 5);
+}
+
+#[test]
+fn transaction_hashes_are_deterministic_and_distinct() {
+    let replay = || {
+        let mut t = ledger();
+        t.transfer(owner(), user(1), 100, Some(vec![1, 2])).unwrap();
+        t.transfer(owner(), user(1), 100, None).unwrap();
+        t
+    };
+    let (a, b) = (replay(), replay());
+    assert_eq!(a.transaction_hash(0), b.transaction_hash(0));
+    assert_eq!(a.transaction_hash(1), b.transaction_hash(1));
+    assert_eq!(a.transaction_hash(0).unwrap().len(), 32);
+    assert_ne!(a.transaction_hash(0), a.transaction_hash(1));
+    assert_eq!(a.transaction_hash(2), None);
+}
+
+#[test]
+fn the_recipient_whitelist_rejects_unlisted_recipients_when_required() {
+    let mut t = ledger();
+    t.set_whitelisted_recipient(user(1), true).unwrap();
+    t.set_require_recipient_whitelist(true).unwrap();
+    assert_eq!(t.transfer(owner(), user(2), 10, None), Err("Recipient is not whitelisted".to_string()));
+    t.transfer(owner(), user(1), 10, None).unwrap();
+    assert_eq!(t.balance_of(user(2)), 0);
+
+    t.set_require_recipient_whitelist(false).unwrap();
+    t.transfer(owner(), user(2), 10, None).unwrap();
+    assert_eq!((t.balance_of(user(1)), t.balance_of(user(2))), (10, 10));
+}
+
+#[test]
+fn method_names_lists_the_core_endpoints_sorted() {
+    let names = method_names();
+    for name in ["approve", "transfer", "transfer_from", "method_names"] {
+        assert!(names.iter().any(|n| n == name), "missing {name}");
+    }
+    assert!(names.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+fn a_mint_fee_splits_the_mint_between_recipient_and_treasury() {
+    let mut t = ledger();
+    t.set_fee_collector(user(9)).unwrap();
+    t.set_mint_fee_bps(500).unwrap();
+    t.mint(user(1), 1_000, None).unwrap();
+    assert_eq!((t.balance_of(user(1)), t.balance_of(user(9))), (950, 50));
+    assert_eq!(t.total_supply(), 1_001_000);
+}
+
+#[test]
+fn a_force_revoked_allowance_reads_zero() {
+    let mut t = ledger();
+    t.approve(user(1), user(2), 100, None, None).unwrap();
+    t.approve(user(1), user(3), 50, None, None).unwrap();
+    let logged = t.transaction_history.len();
+
+    env::set_caller(user(1));
+    assert_eq!(
+        t.force_revoke_allowance(user(1), user(2)),
+        Err("Only the owner can force-revoke allowances".to_string())
+    );
+    env::set_caller(owner());
+    t.force_revoke_allowance(user(1), user(2)).unwrap();
+    assert_eq!((t.allowance(user(1), user(2)), t.allowance(user(1), user(3))), (0, 50));
+    assert_eq!(t.transaction_history.len(), logged + 1);
+    assert_eq!(t.force_revoke_allowance(user(1), user(2)), Err("No allowance to revoke".to_string()));
+}
+
+#[test]
+fn force_revoke_spender_cuts_the_spender_off_from_every_owner() {
+    let mut t = ledger();
+    for n in 1..=3 {
+        t.approve(user(n), user(7), 100 * n as u128, None, None).unwrap();
+    }
+    t.approve(user(1), user(8), 40, None, None).unwrap();
+
+    assert_eq!(t.force_revoke_spender(user(7)), Ok(3));
+    assert!((1..=3).all(|n| t.allowance(user(n), user(7)) == 0));
+    assert_eq!(t.total_allowance_for_spender(user(7)), 0);
+    assert_eq!(t.allowance(user(1), user(8)), 40);
+    assert_eq!(t.force_revoke_spender(user(7)), Ok(0));
+}
+
+#[test]
+fn frozen_balances_are_masked_only_when_hiding_is_on() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 300, None).unwrap();
+    t.lock_account_until(user(1), 5_000).unwrap();
+    let account = Account { owner: user(1), subaccount: None };
+    assert_eq!(t.icrc1_balance_of(&account), 300);
+
+    t.set_hide_frozen_balances(true).unwrap();
+    assert_eq!(t.icrc1_balance_of(&account), 0);
+    assert_eq!(t.balance_of(user(1)), 300);
+    assert_eq!(t.icrc1_balance_of(&Account { owner: user(2), subaccount: None }), 0);
+
+    env::set_time(5_000);
+    assert_eq!(t.icrc1_balance_of(&account), 300);
+}
+
+#[test]
+fn simulate_transfer_predicts_an_insufficient_balance_failure() {
+    let mut t = ledger();
+    t.set_fee(5).unwrap();
+    t.transfer(owner(), user(1), 100, None).unwrap();
+
+    assert_eq!(
+        t.simulate_transfer(user(1), user(2), 96),
+        SimulationResult {
+            would_succeed: false,
+            fee: 5,
+            post_balance_from: 100,
+            error: Some("Insufficient balance".to_string()),
+        }
+    );
+    assert_eq!(t.transfer(user(1), user(2), 96, None), Err("Insufficient balance".to_string()));
+    assert_eq!(
+        t.simulate_transfer(user(1), user(2), 95),
+        SimulationResult { would_succeed: true, fee: 5, post_balance_from: 0, error: None }
+    );
+    t.transfer(user(1), user(2), 95, None).unwrap();
+    assert_eq!(t.balance_of(user(1)), 0);
+}
+
+#[test]
+fn adding_a_minter_beyond_the_cap_is_rejected() {
+    let mut t = ledger();
+    t.set_max_minters(Some(2)).unwrap();
+    t.add_minter(user(1)).unwrap();
+    assert_eq!(t.add_minter(user(2)), Err("Minter limit reached".to_string()));
+    assert!(!t.minters.contains(&user(2)));
+    t.add_minter(user(1)).unwrap();
+    assert_eq!(
+        t.set_max_minters(Some(1)),
+        Err("Minter limit is below the current minter count".to_string())
+    );
+}
+
+#[test]
+fn account_statement_keeps_the_range_in_chronological_order() {
+    let mut t = ledger();
+    for (time, amount) in [(1_000, 1), (2_000, 2), (3_000, 3), (4_000, 4)] {
+        env::set_time(time);
+        t.transfer(owner(), user(1), amount, None).unwrap();
+    }
+    env::set_time(2_500);
+    t.transfer(owner(), user(2), 99, None).unwrap();
+    env::set_time(3_500);
+    t.transfer(user(1), user(2), 5, None).unwrap();
+
+    let statement: Vec<(u64, u128)> = t
+        .account_statement(user(1), 2_000, 4_000)
+        .iter()
+        .map(|r| (r.timestamp, r.amount))
+        .collect();
+    assert_eq!(statement, vec![(2_000, 2), (3_000, 3), (3_500, 5)]);
+    assert!(t.account_statement(user(1), 5_000, 9_000).is_empty());
+}
+
+#[test]
+fn a_large_burn_needs_confirmation_while_a_small_one_runs_at_once() {
+    let mut t = ledger();
+    t.set_large_burn_threshold(Some(100)).unwrap();
+    t.burn(owner(), 100).unwrap();
+    assert_eq!(t.total_supply(), 999_900);
+
+    assert_eq!(t.burn(owner(), 500), Err("Large burn requires propose_burn and confirm_burn".to_string()));
+    t.propose_burn(owner(), 500).unwrap();
+    assert_eq!((t.total_supply(), t.pending_burn(owner())), (999_900, Some(500)));
+    assert_eq!(t.confirm_burn(owner(), 400), Err("Pending burn is for 500".to_string()));
+    t.confirm_burn(owner(), 500).unwrap();
+    assert_eq!((t.total_supply(), t.balance_of(owner())), (999_400, 999_400));
+    assert_eq!(t.pending_burn(owner()), None);
+}
+
+#[test]
+fn distribution_stats_on_a_small_synthetic_distribution() {
+    let mut t = ledger();
+    for n in 1..=19 {
+        t.transfer(owner(), user(n), 20_000, None).unwrap();
+    }
+    assert_eq!(
+        t.distribution_stats(),
+        Ok(DistributionStats { holder_count: 20, top1_bps: 6_200, top10_bps: 6_400, gini_bps: 5_700 })
+    );
+
+    let mut even = ledger();
+    for n in 1..=3 {
+        even.transfer(owner(), user(n), 250_000, None).unwrap();
+    }
+    assert_eq!(
+        even.distribution_stats(),
+        Ok(DistributionStats { holder_count: 4, top1_bps: 2_500, top10_bps: 2_500, gini_bps: 0 })
+    );
+}
+
+#[test]
+fn non_whitelisted_senders_wait_for_trading_to_be_enabled() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 100, None).unwrap();
+    t.transfer(owner(), user(2), 100, None).unwrap();
+    t.set_trading_enabled(false).unwrap();
+    t.set_trading_whitelisted(user(2), true).unwrap();
+
+    assert_eq!(t.transfer(user(1), user(3), 10, None), Err("Trading is not enabled yet".to_string()));
+    t.transfer(user(2), user(3), 10, None).unwrap();
+    t.transfer(owner(), user(3), 10, None).unwrap();
+
+    t.set_trading_enabled(true).unwrap();
+    t.transfer(user(1), user(3), 10, None).unwrap();
+    assert_eq!((t.balance_of(user(1)), t.balance_of(user(3))), (90, 30));
+}
+
+#[test]
+fn accounts_above_filters_by_threshold_largest_first() {
+    let mut t = ledger();
+    t.set_fee_collector(user(9)).unwrap();
+    for (n, amount) in [(1, 500), (2, 300), (3, 300), (4, 100), (9, 400)] {
+        t.transfer(owner(), user(n), amount, None).unwrap();
+    }
+
+    assert_eq!(
+        t.accounts_above(300, false, 0, 10),
+        vec![(owner(), 998_400), (user(1), 500), (user(9), 400), (user(2), 300), (user(3), 300)]
+    );
+    assert_eq!(t.accounts_above(300, true, 0, 10), vec![(user(1), 500), (user(2), 300), (user(3), 300)]);
+    assert_eq!(t.accounts_above(300, true, 1, 1), vec![(user(2), 300)]);
+    assert!(t.accounts_above(2_000_000, false, 0, 10).is_empty());
+}
+
+#[test]
+fn pending_operations_lists_every_open_two_step_operation() {
+    let mut t = two_of_three_minters();
+    t.transfer(owner(), user(4), 100, None).unwrap();
+    t.transfer(owner(), user(5), 300, None).unwrap();
+    t.set_inactivity_period(Some(MIN_INACTIVITY_PERIOD_NS)).unwrap();
+    let now = 1_000 + MIN_INACTIVITY_PERIOD_NS;
+    env::set_time(now);
+    t.propose_reclaim(user(4), user(6)).unwrap();
+
+    let id = t.propose_mint(user(7), 500, None).unwrap();
+    t.hold(user(5), 50).unwrap();
+    t.propose_burn(user(5), 200).unwrap();
+
+    let pending = t.pending_operations();
+    let proposals: Vec<(u64, Principal, u64)> =
+        pending.mint_proposals.iter().map(|p| (p.id, p.proposer, p.expires_at)).collect();
+    assert_eq!(proposals, vec![(id, owner(), now + t.mint_proposal_ttl_ns)]);
+    assert_eq!(pending.holds, vec![(user(5), 50)]);
+    assert_eq!(pending.burns, vec![(user(5), 200)]);
+    assert_eq!(pending.reclaims, vec![(user(4), user(6))]);
+
+    t.confirm_burn(user(5), 200).unwrap();
+    assert!(t.pending_operations().burns.is_empty());
+}
+
+#[test]
+fn a_mint_without_a_custody_ref_is_rejected_when_required() {
+    let mut t = ledger();
+    t.set_require_custody_ref(true).unwrap();
+    assert_eq!(t.mint(user(1), 100, None), Err("Custody reference required".to_string()));
+    assert_eq!(t.mint(user(1), 100, Some(String::new())), Err("Custody reference required".to_string()));
+    assert_eq!(t.total_supply(), 1_000_000);
+
+    t.mint(user(1), 100, Some("ISSUE-42".to_string())).unwrap();
+    assert_eq!(t.balance_of(user(1)), 100);
+    assert_eq!(t.transaction_history.last().unwrap().memo, Some(b"ISSUE-42".to_vec()));
+}
+
+#[test]
+fn the_certified_data_changes_when_metadata_changes() {
+    let mut t = ledger();
+    let before = t.certified_data();
+    assert_eq!(before, ledger().certified_data());
+    assert_eq!(before, metadata_hash("TKN", "Token", 8));
+
+    t.migrate_decimals(6).unwrap();
+    assert_ne!(t.certified_data(), before);
+    assert_eq!(t.certified_data(), metadata_hash("TKN", "Token", 6));
+}
+
+#[test]
+fn reassign_allowance_moves_the_amount_and_expiry_to_the_new_spender() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 500, None).unwrap();
+    t.approve(user(1), user(2), 200, Some(9_000), None).unwrap();
+
+    assert_eq!(t.reassign_allowance(user(1), user(2), user(3)), Ok(200));
+    assert_eq!((t.allowance(user(1), user(2)), t.allowance(user(1), user(3))), (0, 200));
+    assert_eq!(t.allowance_expiries.get(&(user(1), user(3))), Some(&9_000));
+    assert!(!t.allowance_expiries.contains_key(&(user(1), user(2))));
+    assert_eq!(t.reassign_allowance(user(1), user(2), user(3)), Err("No allowance to reassign".to_string()));
+
+    env::set_caller(user(3));
+    t.transfer_from(user(3), user(1), user(4), 200, None).unwrap();
+    assert_eq!(t.balance_of(user(4)), 200);
+}
+
+#[test]
+fn a_repeated_memo_to_one_recipient_within_the_window_is_rejected() {
+    let mut t = ledger();
+    t.set_memo_uniqueness_window(Some(1_000)).unwrap();
+    t.transfer(owner(), user(1), 10, Some(vec![7])).unwrap();
+
+    env::set_time(1_500);
+    assert_eq!(t.transfer(owner(), user(1), 10, Some(vec![7])), Err("Duplicate memo".to_string()));
+    t.transfer(owner(), user(2), 10, Some(vec![7])).unwrap();
+    t.transfer(owner(), user(1), 10, Some(vec![8])).unwrap();
+
+    env::set_time(2_001);
+    t.transfer(owner(), user(1), 10, Some(vec![7])).unwrap();
+    assert_eq!(t.balance_of(user(1)), 30);
+}
+
+#[test]
+fn set_config_and_config_round_trip() {
+    let mut t = ledger();
+    let config = LedgerConfig {
+        fee: 5,
+        fee_free_below: 20,
+        fee_payer: FeePayer::Recipient,
+        approve_fee: 2,
+        min_approval_amount: 10,
+        transfer_from_fee: Some(7),
+        fee_collector: user(9),
+        rounding_mode: RoundingMode::Up,
+        mint_fee_bps: 250,
+        max_minters: Some(4),
+        require_custody_ref: true,
+        max_tx_amount: Some(10_000),
+        max_wallet: Some(50_000),
+        max_allowance: Some(30_000),
+        existential_deposit: 3,
+        require_memo: true,
+        memo_uniqueness_window_ns: Some(60),
+        require_recipient_whitelist: true,
+        trading_enabled: false,
+        hide_frozen_balances: true,
+        large_burn_threshold: Some(1_000),
+        inactivity_period_ns: Some(MIN_INACTIVITY_PERIOD_NS),
+        rate_limit: Some(RateLimit { max_ops: 5, window_ns: 100 }),
+        feature_flags: FeatureFlags { burn: false, ..FeatureFlags::default() },
+        pause_mode: PauseMode::Queue,
+        deflation: Some(DeflationSchedule { rate_bps: 10, interval_ns: 1_000 }),
+        event_log_capacity: 50,
+        ..t.config()
+    };
+    t.set_config(config.clone()).unwrap();
+    assert_eq!(t.config(), config);
+    assert_eq!((t.fee, t.fee_collector, t.pause_mode), (5, user(9), PauseMode::Queue));
+
+    let bad = LedgerConfig { mint_fee_bps: 10_001, fee: 99, ..config.clone() };
+    assert_eq!(t.set_config(bad), Err("Mint fee cannot exceed 10000 bps".to_string()));
+    env::set_caller(user(1));
+    assert_eq!(
+        t.set_config(LedgerConfig { fee: 99, ..config.clone() }),
+        Err("Only the owner can set the configuration".to_string())
+    );
+    assert_eq!(t.config(), config);
+}
+
+#[test]
+fn find_duplicate_transactions_flags_only_identical_records() {
+    let mut clean = ledger();
+    clean.transfer(owner(), user(1), 10, Some(vec![1])).unwrap();
+    clean.transfer(owner(), user(1), 10, Some(vec![2])).unwrap();
+    env::set_time(2_000);
+    clean.transfer(owner(), user(1), 10, Some(vec![1])).unwrap();
+    assert!(clean.find_duplicate_transactions().is_empty());
+
+    let mut t = ledger();
+    let first = t.transaction_history.len() as u64;
+    t.transfer(owner(), user(1), 10, Some(vec![1])).unwrap();
+    t.transfer(owner(), user(2), 10, Some(vec![1])).unwrap();
+    t.transfer(owner(), user(1), 10, Some(vec![1])).unwrap();
+    assert_eq!(t.find_duplicate_transactions(), vec![(first, first + 2)]);
+}
+
+#[test]
+fn net_flow_nets_sends_against_receives() {
+    let mut t = ledger();
+    t.set_fee_collector(user(9)).unwrap();
+    t.set_fee(2).unwrap();
+    t.transfer(owner(), user(1), 100, None).unwrap();
+    env::set_time(2_000);
+    t.transfer(user(1), user(2), 30, None).unwrap();
+    env::set_time(3_000);
+    t.transfer(user(2), user(1), 15, None).unwrap();
+
+    assert_eq!(t.net_flow(user(1), 0, 10_000), 83);
+    assert_eq!(t.net_flow(user(1), 0, 10_000), t.balance_of(user(1)) as i128);
+    assert_eq!(t.net_flow(user(1), 2_000, 10_000), -17);
+    assert_eq!(t.net_flow(user(2), 0, 10_000), 13);
+    assert_eq!(t.net_flow(user(1), 5_000, 10_000), 0);
+}
+
+#[test]
+fn a_tiny_approval_is_rejected_while_a_zero_approval_revokes() {
+    let mut t = ledger();
+    t.approve(user(1), user(2), 500, None, None).unwrap();
+    t.set_min_approval_amount(100).unwrap();
+
+    assert_eq!(
+        t.approve(user(1), user(2), 99, None, None),
+        Err("Approval is below the minimum of 100".to_string())
+    );
+    assert_eq!(t.allowance(user(1), user(2)), 500);
+    t.approve(user(1), user(2), 100, None, None).unwrap();
+    assert_eq!(t.allowance(user(1), user(2)), 100);
+    t.approve(user(1), user(2), 0, None, None).unwrap();
+    assert_eq!(t.allowance(user(1), user(2)), 0);
+}
+
+#[test]
+fn icrc10_supported_standards_includes_icrc10() {
+    let mut t = ledger();
+    let names = |t: &TokenICRC2| -> Vec<String> { t.icrc10_supported_standards().into_iter().map(|s| s.name).collect() };
+    assert_eq!(names(&t), vec!["ICRC-1", "ICRC-2", "ICRC-10"]);
+
+    t.set_feature_flags(FeatureFlags { transfer_from: false, ..t.feature_flags() }).unwrap();
+    assert_eq!(names(&t), vec!["ICRC-1", "ICRC-10"]);
+}
+
+#[test]
+fn fee_payer_modes_move_the_fee_to_the_right_side() {
+    for (payer, sender_left, received) in [(FeePayer::Sender, 890, 100), (FeePayer::Recipient, 900, 90)] {
+        let mut t = ledger();
+        t.set_fee_collector(user(9)).unwrap();
+        t.transfer(owner(), user(1), 1_000, None).unwrap();
+        t.set_fee(10).unwrap();
+        t.set_fee_payer(payer).unwrap();
+        assert_eq!(t.received_amount(100), received);
+
+        t.transfer(user(1), user(2), 100, None).unwrap();
+        assert_eq!((t.balance_of(user(1)), t.balance_of(user(2)), t.balance_of(user(9))), (sender_left, received, 10));
+    }
+
+    let mut t = ledger();
+    t.set_fee(10).unwrap();
+    t.set_fee_payer(FeePayer::Recipient).unwrap();
+    assert_eq!(
+        t.transfer(owner(), user(2), 10, None),
+        Err("Amount must exceed the fee when the recipient pays it".to_string())
+    );
+}
+
+#[test]
+fn approval_count_skips_expired_spent_and_zero_approvals() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 1_000, None).unwrap();
+    t.approve(user(1), user(2), 100, None, None).unwrap();
+    t.approve(user(1), user(2), 150, None, None).unwrap();
+    t.approve(user(1), user(3), 100, None, None).unwrap();
+    t.approve(user(1), user(3), 0, None, None).unwrap();
+    t.approve(user(1), user(4), 50, None, None).unwrap();
+    t.approve(user(1), user(5), 100, Some(2_000), None).unwrap();
+    t.approve(user(1), user(6), 100, Some(3_000), None).unwrap();
+    env::set_caller(user(4));
+    t.transfer_from(user(4), user(1), user(7), 50, None).unwrap();
+
+    env::set_time(2_000);
+    assert_eq!(t.approval_count(user(1)), 2);
+    env::set_time(3_000);
+    assert_eq!(t.approval_count(user(1)), 1);
+    assert_eq!(t.approval_count(user(2)), 0);
+}
+
+#[test]
+fn transactions_by_index_fill_one_slot_per_requested_index() {
+    let mut t = ledger();
+    let base = t.transaction_history.len() as u64;
+    for amount in [10, 20, 30] {
+        t.transfer(owner(), user(1), amount, None).unwrap();
+    }
+
+    let amounts: Vec<Option<u128>> = t
+        .get_transactions_by_index(&[base + 2, 999, base, u64::MAX, base + 2])
+        .into_iter()
+        .map(|r| r.map(|r| r.amount))
+        .collect();
+    assert_eq!(amounts, vec![Some(30), None, Some(10), None, Some(30)]);
+    assert_eq!(t.get_transactions_by_index(&[base; 150]).len(), 100);
+    assert!(t.get_transactions_by_index(&[]).is_empty());
+}
+
+#[test]
+fn run_deflation_burns_the_scheduled_share_of_the_treasury() {
+    let mut t = ledger();
+    t.set_fee_collector(user(9)).unwrap();
+    t.transfer(owner(), user(9), 10_000, None).unwrap();
+    t.set_deflation_schedule(Some(DeflationSchedule { rate_bps: 100, interval_ns: 1_000 })).unwrap();
+
+    env::set_time(1_500);
+    assert_eq!(t.run_deflation(), 0);
+    env::set_time(2_000);
+    assert_eq!(t.run_deflation(), 100);
+    assert_eq!((t.balance_of(user(9)), t.total_supply()), (9_900, 999_900));
+    env::set_time(2_500);
+    assert_eq!(t.run_deflation(), 0);
+    env::set_time(9_000);
+    assert_eq!(t.run_deflation(), 99);
+    assert_eq!(t.total_supply(), 999_801);
+}
+
+#[test]
+fn the_first_init_returns_the_token_and_a_second_init_errors() {
+    env::set_time(1_000);
+    let sym = |s: &str| s.to_string();
+    assert_eq!(
+        install_token(owner(), sym(" "), sym("Token"), 1_000, 8, None).err(),
+        Some("Symbol and name must be nonempty".to_string())
+    );
+    assert!(TOKEN_ICRC2.with(|token| token.borrow().is_none()));
+
+    let info = install_token(owner(), sym("TKN"), sym("Token"), 1_000, 8, Some(vec![(user(1), 400)])).unwrap();
+    assert_eq!((info.symbol.as_str(), info.name.as_str(), info.decimals), ("TKN", "Token", 8));
+    assert_eq!((info.total_supply, info.owner), (1_000, owner()));
+    assert_eq!((balance_of(user(1)), balance_of(owner())), (400, 600));
+
+    assert_eq!(
+        install_token(user(2), sym("NEW"), sym("Other"), 5, 2, None).err(),
+        Some("Token already initialized".to_string())
+    );
+    let installed = TOKEN_ICRC2.with(|token| token.borrow().as_ref().map(|t| (t.owner, t.symbol.clone())));
+    assert_eq!(installed, Some((owner(), sym("TKN"))));
+}
+
+#[test]
+fn the_mint_hook_is_notified_after_each_mint_credit() {
+    let mut t = ledger();
+    t.set_fee_collector(user(9)).unwrap();
+    t.set_mint_fee_bps(1_000).unwrap();
+    t.mint(user(1), 100, None).unwrap();
+    assert!(env::take_mint_notifications().is_empty());
+
+    t.set_mint_hook(Some(user(8))).unwrap();
+    t.mint(user(1), 1_000, None).unwrap();
+    let base = t.transaction_history.len() as u64 - 2;
+    assert_eq!(
+        env::take_mint_notifications(),
+        vec![(user(8), (user(1), 900, base)), (user(8), (user(9), 100, base + 1))]
+    );
+    let record = &t.transaction_history[base as usize];
+    assert_eq!((record.kind, record.to, record.amount), (TxKind::Mint, user(1), 900));
+}
+
+#[test]
+fn balance_value_in_icp_uses_the_cached_price() {
+    let mut t = ledger();
+    t.mint(user(1), 250_000_000, None).unwrap();
+    assert_eq!(t.balance_value_in_icp(user(1)), Err("No price oracle configured".to_string()));
+
+    t.set_price_oracle(Some(user(8))).unwrap();
+    t.record_price(40_000_000);
+    assert_eq!(t.balance_value_in_icp(user(1)), Ok(100_000_000));
+    assert_eq!(t.balance_value_in_icp(user(2)), Ok(0));
+
+    env::set_time(1_000 + t.price_ttl_ns);
+    assert_eq!(t.balance_value_in_icp(user(1)), Err("Price is stale or unavailable".to_string()));
+}
+
+#[test]
+fn overflowing_the_event_log_keeps_the_newest_and_counts_the_dropped() {
+    let mut t = ledger();
+    t.set_event_log_capacity(3).unwrap();
+    for n in 1..=5u8 {
+        let _ = t.log_failure::<()>("transfer", user(n), Err(format!("Failure {}", n)));
+    }
+
+    let kept: Vec<(Principal, String)> = t.get_failed_operations(0, 10).into_iter().map(|f| (f.caller, f.error)).collect();
+    assert_eq!(
+        kept,
+        vec![(user(3), "Failure 3".to_string()), (user(4), "Failure 4".to_string()), (user(5), "Failure 5".to_string())]
+    );
+    assert_eq!(t.dropped_events(), 2);
+    assert_eq!(t.error_counts().len(), 5);
+
+    t.set_event_log_capacity(1).unwrap();
+    assert_eq!(t.get_failed_operations(0, 10).len(), 1);
+    assert_eq!(t.dropped_events(), 4);
+}
+
+#[test]
+fn have_transacted_is_true_only_for_pairs_that_shared_a_record() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 100, None).unwrap();
+    t.transfer(owner(), user(2), 100, None).unwrap();
+    t.transfer(user(1), user(3), 10, None).unwrap();
+
+    assert!(t.have_transacted(user(1), user(3)) && t.have_transacted(user(3), user(1)));
+    assert!(!t.have_transacted(user(1), user(2)));
+    assert!(!t.have_transacted(user(2), user(3)));
+    assert!(!t.have_transacted(user(1), user(7)));
+
+    assert!(!t.have_transacted(user(1), user(1)));
+    t.transfer(user(1), user(1), 5, None).unwrap();
+    assert!(t.have_transacted(user(1), user(1)));
+}
+
+#[test]
+fn a_renounced_allowance_reads_zero_for_the_owner() {
+    let mut t = ledger();
+    t.transfer(owner(), user(1), 500, None).unwrap();
+    t.approve(user(1), user(2), 200, None, None).unwrap();
+    t.approve(user(1), user(3), 100, None, None).unwrap();
+
+    env::set_caller(user(2));
+    t.renounce_allowance(user(1)).unwrap();
+    assert_eq!((t.allowance(user(1), user(2)), t.allowance(user(1), user(3))), (0, 100));
+    assert!(t.transfer_from(user(2), user(1), user(4), 10, None).is_err());
+    assert_eq!(t.renounce_allowance(user(1)), Err("No allowance to renounce".to_string()));
+    assert_eq!(t.renounce_allowance(user(5)), Err("No allowance to renounce".to_string()));
+}
+
+#[test]
+fn cycles_by_operation_sums_each_kind_over_a_mixed_history() {
+    let mut t = ledger();
+    let message = |time: u64, instructions: u64| {
+        env::set_time(time);
+        env::set_instruction_counter(instructions);
+    };
+    message(2_000, 1_000);
+    t.transfer(owner(), user(1), 100, None).unwrap();
+    message(3_000, 500);
+    t.approve(owner(), user(2), 50, None, None).unwrap();
+    message(4_000, 250);
+    t.mint(user(1), 10, None).unwrap();
+    message(5_000, 500);
+    t.transfer(user(1), user(3), 10, None).unwrap();
+    message(6_000, 2_500);
+    t.burn(owner(), 5).unwrap();
+
+    assert_eq!(
+        t.cycles_by_operation(),
+        vec![(TxKind::Transfer, 600), (TxKind::Mint, 100), (TxKind::Burn, 1_000), (TxKind::Approve, 200)]
+    );
+}
@@ -1,3 +1,7 @@
+pub mod amm;
+pub mod icrc2;
+pub mod merkle;
+
 #[ic_cdk::query]
 fn greet(name: String) -> String {
     format!("Hello, {}!", name)
@@ -0,0 +1,119 @@
+use candid::Principal;
+use sha2::{Digest, Sha256};
+
+// Binary Merkle tree over (principal, balance) leaves. Leaves and inner nodes
+// are domain-separated, and an odd node at the end of a level is carried up
+// unchanged. Proof steps are 33 bytes: a side byte (0 = sibling on the left,
+// 1 = sibling on the right) followed by the sibling hash.
+
+pub fn leaf_hash(principal: &Principal, balance: u128) -> [u8; 32] {
+    let bytes = principal.as_slice();
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update([bytes.len() as u8]);
+    hasher.update(bytes);
+    hasher.update(balance.to_be_bytes());
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+// Root of the tree, or the hash of nothing for an empty leaf set
+pub fn root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return Sha256::digest([]).into();
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level[0]
+}
+
+pub fn proof(leaves: &[[u8; 32]], mut index: usize) -> Vec<Vec<u8>> {
+    let mut steps = Vec::new();
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            let side = if sibling < index { 0u8 } else { 1u8 };
+            let mut step = vec![side];
+            step.extend_from_slice(&level[sibling]);
+            steps.push(step);
+        }
+        level = next_level(&level);
+        index /= 2;
+    }
+    steps
+}
+
+pub fn verify(leaf: [u8; 32], proof: &[Vec<u8>], root: &[u8]) -> bool {
+    let mut current = leaf;
+    for step in proof {
+        let Ok(sibling) = <[u8; 32]>::try_from(&step[1.min(step.len())..]) else {
+            return false;
+        };
+        current = match step[0] {
+            0 => node_hash(&sibling, &current),
+            1 => node_hash(&current, &sibling),
+            _ => return false,
+        };
+    }
+    current.as_slice() == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: u8) -> Vec<[u8; 32]> {
+        (0..n).map(|i| leaf_hash(&Principal::from_slice(&[i]), 100 + i as u128)).collect()
+    }
+
+    #[test]
+    fn every_leaf_proves_against_the_root() {
+        for n in 1..=9 {
+            let leaves = leaves(n);
+            let root = root(&leaves);
+            for (i, leaf) in leaves.iter().enumerate() {
+                assert!(verify(*leaf, &proof(&leaves, i), &root), "leaf {} of {}", i, n);
+            }
+        }
+    }
+
+    #[test]
+    fn a_proof_fails_for_another_balance() {
+        let leaves = leaves(5);
+        let forged = leaf_hash(&Principal::from_slice(&[2]), 1_000);
+        assert!(!verify(forged, &proof(&leaves, 2), &root(&leaves)));
+    }
+
+    #[test]
+    fn a_malformed_proof_step_fails() {
+        let leaves = leaves(4);
+        let root = root(&leaves);
+        let mut steps = proof(&leaves, 1);
+        steps[0][0] = 2;
+        assert!(!verify(leaves[1], &steps, &root));
+        steps[0] = vec![0; 20];
+        assert!(!verify(leaves[1], &steps, &root));
+        assert!(!verify(leaves[1], &[Vec::new()], &root));
+    }
+}